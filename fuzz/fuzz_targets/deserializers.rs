@@ -0,0 +1,44 @@
+//! Feeds arbitrary JSON to each public type which can be deserialized on its
+//! own, as well as to `Tileset::try_from`.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_json;
+extern crate tiled_json;
+
+use std::convert::TryFrom;
+use serde_json::Value as JsonValue;
+use tiled_json::{GlobalTile, LocalTile};
+use tiled_json::color::Color;
+use tiled_json::layer::{ImageLayer, Layer, Object, ObjectLayer, TileLayer};
+use tiled_json::properties::Properties;
+use tiled_json::tileset::Tileset;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(json) = ::std::str::from_utf8(data) {
+        let _ = Tileset::try_from(json);
+    }
+    
+    // serde_json recurses while parsing, so very deeply nested input would
+    // overflow the stack here rather than in the crate. The crate's own
+    // nesting limit is covered by the load_level target.
+    if data.len() > 4096 {
+        return;
+    }
+    let value: JsonValue = match serde_json::from_slice(data) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    
+    let _ = serde_json::from_value::<Layer>(value.clone());
+    let _ = serde_json::from_value::<TileLayer>(value.clone());
+    let _ = serde_json::from_value::<ObjectLayer>(value.clone());
+    let _ = serde_json::from_value::<ImageLayer>(value.clone());
+    let _ = serde_json::from_value::<Object>(value.clone());
+    let _ = serde_json::from_value::<Tileset>(value.clone());
+    let _ = serde_json::from_value::<Properties>(value.clone());
+    let _ = serde_json::from_value::<Color>(value.clone());
+    let _ = serde_json::from_value::<GlobalTile>(value.clone());
+    let _ = serde_json::from_value::<LocalTile>(value);
+});
@@ -0,0 +1,35 @@
+//! Loads arbitrary bytes as a level with each set of options that changes how
+//! loading behaves, and checks loaded levels with `Level::validate`.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate tiled_json;
+
+use tiled_json::level::Level;
+use tiled_json::options::{GidIntegrity, LoadOptions};
+
+fuzz_target!(|data: &[u8]| {
+    let strict = LoadOptions {
+        gid_integrity: GidIntegrity::Error,
+        strict_firstgid: true,
+        ..LoadOptions::default()
+    };
+    let lenient = LoadOptions {
+        lenient_layer_data: true,
+        keep_raw_objects: true,
+        coerce_legacy_properties: true,
+        allow_partial: true,
+        record_source_pointers: true,
+        ..LoadOptions::default()
+    };
+    
+    for options in &[LoadOptions::default(), strict, lenient] {
+        if let Ok(level) = Level::load_from_slice(data, options) {
+            let _ = level.validate();
+        }
+    }
+    if let Ok(json) = ::std::str::from_utf8(data) {
+        let _ = Level::validate_schema(json);
+    }
+});
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Deserializer};
+
+/// An RGBA color as written by Tiled
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    /// Parses a color in Tiled's `#RRGGBB` or `#AARRGGBB` format. The leading
+    /// `#` is optional.
+    pub fn parse(text: &str) -> Option<Color> {
+        let hex = text.strip_prefix('#').unwrap_or(text);
+        
+        // from_str_radix would accept a leading sign, which isn't valid here
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = match u32::from_str_radix(hex, 16) {
+            Ok(value) => value,
+            Err(_) => return None,
+        };
+        
+        let alpha = match hex.len() {
+            6 => 0xFF,
+            8 => (value >> 24) as u8,
+            _ => return None,
+        };
+        
+        Some(Color {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+            a: alpha,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        
+        let text = String::deserialize(d)?;
+        match Color::parse(&text) {
+            Some(color) => Ok(color),
+            None => Err(D::Error::custom(format!("`{}` is not a valid color", text))),
+        }
+    }
+}
+
+#[test]
+fn parse_colors() {
+    assert_eq!(Color::parse("#a0a0a4"), Some(Color { r: 0xA0, g: 0xA0, b: 0xA4, a: 0xFF }));
+    assert_eq!(Color::parse("80ff0000"), Some(Color { r: 0xFF, g: 0, b: 0, a: 0x80 }));
+    assert_eq!(Color::parse("#+12345"), None);
+    assert_eq!(Color::parse("#1234"), None);
+}
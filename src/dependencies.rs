@@ -0,0 +1,153 @@
+//! Finding the files a level depends on by scanning its JSON, without
+//! loading it.
+
+use std::path::{Path, PathBuf};
+use serde_json::Value as JsonValue;
+use serde_json::Error as JsonError;
+use {read_json, resolve_relative};
+
+/// The files found by `Level::dependencies`
+#[derive(Clone, Debug, Default)]
+pub struct Dependencies {
+    /// Every file referenced by the level or by the files it references,
+    /// without duplicates, in the order they were found. Paths are resolved
+    /// the same way loading resolves them.
+    pub files: Vec<PathBuf>,
+    /// Referenced files which don't exist or couldn't be read
+    pub warnings: Vec<String>,
+}
+
+impl Dependencies {
+    /// Records a referenced file, returning whether it exists and hasn't been
+    /// seen before, i.e. whether it should be scanned itself
+    fn add(&mut self, path: PathBuf, from: &Path) -> bool {
+        if self.files.contains(&path) {
+            return false;
+        }
+        let exists = path.exists();
+        if !exists {
+            self.warnings.push(format!("{} references {}, which doesn't exist", from.display(), path.display()));
+        }
+        self.files.push(path);
+        exists
+    }
+    
+    fn read(&mut self, path: &Path) -> Option<JsonValue> {
+        match read_json(path) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.warnings.push(format!("Couldn't read {}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+    
+    /// Scans a tileset, inline in `file` or in its own file
+    fn tileset(&mut self, file: &Path, tileset: &JsonValue) {
+        if let Some(source) = tileset.get("source").and_then(JsonValue::as_str) {
+            let path = resolve_relative(file, source);
+            if self.add(path.clone(), file) {
+                if let Some(external) = self.read(&path) {
+                    self.tileset(&path, &external);
+                }
+            }
+            return;
+        }
+        
+        self.image(file, tileset);
+        self.properties(file, tileset);
+        // Image collection tilesets have an image per tile
+        if let Some(JsonValue::Object(tiles)) = tileset.get("tiles") {
+            for tile in tiles.values() {
+                self.image(file, tile);
+            }
+        }
+        if let Some(JsonValue::Array(tiles)) = tileset.get("tiles") {
+            for tile in tiles {
+                self.image(file, tile);
+                self.properties(file, tile);
+            }
+        }
+    }
+    
+    fn layer(&mut self, file: &Path, layer: &JsonValue) {
+        self.image(file, layer);
+        self.properties(file, layer);
+        if let Some(JsonValue::Array(objects)) = layer.get("objects") {
+            for object in objects {
+                self.object(file, object);
+            }
+        }
+        // Group layers
+        if let Some(JsonValue::Array(layers)) = layer.get("layers") {
+            for layer in layers {
+                self.layer(file, layer);
+            }
+        }
+    }
+    
+    fn object(&mut self, file: &Path, object: &JsonValue) {
+        self.properties(file, object);
+        if let Some(template) = object.get("template").and_then(JsonValue::as_str) {
+            let path = resolve_relative(file, template);
+            if self.add(path.clone(), file) {
+                if let Some(template) = self.read(&path) {
+                    if let Some(tileset) = template.get("tileset") {
+                        self.tileset(&path, tileset);
+                    }
+                    if let Some(object) = template.get("object") {
+                        self.object(&path, object);
+                    }
+                }
+            }
+        }
+    }
+    
+    fn image(&mut self, file: &Path, value: &JsonValue) {
+        match value.get("image").and_then(JsonValue::as_str) {
+            Some(image) if !image.is_empty() => {
+                self.add(resolve_relative(file, image), file);
+            }
+            _ => {}
+        }
+    }
+    
+    /// Adds file-typed properties, which only the list format declares
+    fn properties(&mut self, file: &Path, value: &JsonValue) {
+        let properties = match value.get("properties") {
+            Some(JsonValue::Array(properties)) => properties,
+            _ => return,
+        };
+        for property in properties {
+            if property.get("type").and_then(JsonValue::as_str) != Some("file") {
+                continue;
+            }
+            match property.get("value").and_then(JsonValue::as_str) {
+                Some(value) if !value.is_empty() => {
+                    self.add(resolve_relative(file, value), file);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Scans the level at `path`. Only failing to read the level itself is an
+/// error; problems with the files it references are warnings.
+pub(crate) fn scan_level(path: &Path) -> Result<Dependencies, JsonError> {
+    let level: JsonValue = read_json(path)?;
+    
+    let mut dependencies = Dependencies::default();
+    dependencies.properties(path, &level);
+    if let Some(JsonValue::Array(layers)) = level.get("layers") {
+        for layer in layers {
+            dependencies.layer(path, layer);
+        }
+    }
+    if let Some(JsonValue::Array(tilesets)) = level.get("tilesets") {
+        for tileset in tilesets {
+            dependencies.tileset(path, tileset);
+        }
+    }
+    Ok(dependencies)
+}
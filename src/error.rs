@@ -0,0 +1,314 @@
+use std::error::Error;
+use std::fmt;
+use serde::de::DeserializeOwned;
+use serde_json::Error as JsonError;
+use serde_json::Value as JsonValue;
+
+/// Errors produced when parsing or manipulating levels and tilesets
+#[derive(Debug)]
+pub enum TiledError {
+    /// The JSON data could not be parsed
+    Json(JsonError),
+    /// A value in the JSON, such as a field of an object, couldn't be
+    /// loaded. The line and column are where the value starts, counting from
+    /// 1, or 0 if they aren't known.
+    InvalidValue { pointer: String, line: usize, column: usize, message: String },
+    /// The data references an external tileset, but it wasn't loaded from a
+    /// file so there is no path to find the tileset relative to
+    ExternalTilesetNotSupported,
+    /// A layer couldn't be resized from one size to another, e.g. because
+    /// `expand` was asked to make it smaller
+    InvalidResize { from: (u32, u32), to: (u32, u32) },
+    /// No layer has the given name
+    LayerNotFound(String),
+    /// More than one layer has the given name, so it doesn't identify a layer
+    AmbiguousLayerName(String),
+    /// A layer can't be given a name which another layer already has
+    LayerNameTaken(String),
+    /// There is no tileset at the given index
+    TilesetNotFound(usize),
+    /// A tileset can't be removed because the level still uses its tiles
+    TilesetInUse(String),
+    /// Tiles can't be remapped to the gid because it isn't a tile of a
+    /// tileset which is staying in the level
+    InvalidRemapTarget(u32),
+    /// The tileset has no tile with the given id, or its gid would overflow
+    TileNotFound { tileset: String, tile: u32 },
+    /// A tileset's settings don't fit together, e.g. its tiles don't fit in
+    /// its image. Holds a description of each problem.
+    InvalidTileset(Vec<String>),
+    /// The operation needs at least one tile layer, but the level has none
+    NoLayers,
+    /// Two layers can't be combined because they are different sizes
+    DimensionMismatch { expected: (u32, u32), found: (u32, u32) },
+    /// Two levels can't be combined because their tiles are different sizes
+    TileSizeMismatch { base: (u32, u32), other: (u32, u32) },
+    /// A transform can't be applied because it can't be undone, e.g. it
+    /// scales by 0
+    NonInvertibleTransform,
+    /// The top-level layers aren't the ones expected, in the order expected
+    LayerOrderViolation { expected: Vec<String>, actual: Vec<String> },
+    /// A JSON patch couldn't be applied, e.g. because nothing in the level
+    /// was loaded from its pointer
+    InvalidPatch { pointer: String, reason: String },
+    /// Objects can't be given new ids because they would go past the largest
+    /// id there can be
+    ObjectIdsExhausted,
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TiledError::Json(ref e) => write!(f, "{}", e),
+            TiledError::InvalidValue { ref pointer, line: 0, ref message, .. } => write!(
+                f, "{} at `{}`: {}", self.summary(), pointer, message
+            ),
+            TiledError::InvalidValue { ref pointer, line, column, ref message } => write!(
+                f, "{} at `{}` (line {} column {}): {}", self.summary(), pointer, line, column, message
+            ),
+            TiledError::InvalidResize { from, to } => write!(
+                f, "{} from {}x{} to {}x{}", self.summary(), from.0, from.1, to.0, to.1
+            ),
+            TiledError::InvalidTileset(ref problems) => write!(f, "{}: {}", self.summary(), problems.join("; ")),
+            TiledError::DimensionMismatch { expected, found } => write!(
+                f, "{}, expected {}x{} but found {}x{}", self.summary(), expected.0, expected.1, found.0, found.1
+            ),
+            TiledError::TileSizeMismatch { base, other } => write!(
+                f, "{}, {}x{} and {}x{}", self.summary(), base.0, base.1, other.0, other.1
+            ),
+            TiledError::LayerOrderViolation { ref expected, ref actual } => write!(
+                f, "{}, expected [{}] but found [{}]", self.summary(), expected.join(", "), actual.join(", ")
+            ),
+            TiledError::InvalidPatch { ref pointer, ref reason } => write!(
+                f, "{} at `{}`: {}", self.summary(), pointer, reason
+            ),
+            TiledError::LayerNotFound(ref name) |
+            TiledError::AmbiguousLayerName(ref name) |
+            TiledError::LayerNameTaken(ref name) |
+            TiledError::TilesetInUse(ref name) => write!(f, "{} `{}`", self.summary(), name),
+            TiledError::TilesetNotFound(index) => write!(f, "{} {}", self.summary(), index),
+            TiledError::InvalidRemapTarget(gid) => write!(f, "{} {}", self.summary(), gid),
+            TiledError::TileNotFound { ref tileset, tile } => write!(f, "{} {} in tileset `{}`", self.summary(), tile, tileset),
+            _ => f.write_str(self.summary()),
+        }
+    }
+}
+
+impl TiledError {
+    /// The message for this kind of error, without any details
+    fn summary(&self) -> &'static str {
+        match *self {
+            TiledError::Json(_) => "Invalid JSON",
+            TiledError::InvalidValue { .. } => "Invalid value",
+            TiledError::ExternalTilesetNotSupported => "External tilesets can only be used when loading from a file",
+            TiledError::InvalidResize { .. } => "Can't resize layer",
+            TiledError::LayerNotFound(_) => "No layer named",
+            TiledError::AmbiguousLayerName(_) => "More than one layer named",
+            TiledError::LayerNameTaken(_) => "There is already a layer named",
+            TiledError::TilesetNotFound(_) => "No tileset at index",
+            TiledError::TilesetInUse(_) => "Tiles are still used from tileset",
+            TiledError::InvalidRemapTarget(_) => "Tiles can't be remapped to gid",
+            TiledError::TileNotFound { .. } => "No tile",
+            TiledError::InvalidTileset(_) => "Invalid tileset",
+            TiledError::NoLayers => "The level has no tile layers",
+            TiledError::DimensionMismatch { .. } => "Layers have different sizes",
+            TiledError::TileSizeMismatch { .. } => "Levels have different tile sizes",
+            TiledError::NonInvertibleTransform => "The transform can't be inverted",
+            TiledError::LayerOrderViolation { .. } => "Layers are not in the expected order",
+            TiledError::InvalidPatch { .. } => "Can't apply JSON patch",
+            TiledError::ObjectIdsExhausted => "There are no object ids left",
+        }
+    }
+    
+    /// Fills in the line and column of an `InvalidValue` error from the JSON
+    /// it was loaded from
+    pub(crate) fn locate(self, json: &[u8]) -> TiledError {
+        match self {
+            TiledError::InvalidValue { pointer, message, .. } => {
+                let (line, column) = pointer_position(json, &pointer).unwrap_or((0, 0));
+                TiledError::InvalidValue { pointer, line, column, message }
+            }
+            e => e,
+        }
+    }
+    
+    /// Turns this back into a JSON error for the functions which return one.
+    /// Only the message is kept, unless it was a JSON error to begin with.
+    pub(crate) fn into_json(self) -> JsonError {
+        use serde::de::Error;
+        match self {
+            TiledError::Json(e) => e,
+            e => JsonError::custom(e.to_string()),
+        }
+    }
+}
+
+impl Error for TiledError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            TiledError::Json(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<JsonError> for TiledError {
+    fn from(e: JsonError) -> TiledError {
+        TiledError::Json(e)
+    }
+}
+
+/// Prefixes an error message with the JSON pointer of the value it came from.
+/// Messages which already start with a pointer (from a nested value) are
+/// joined onto it, so that errors read like
+/// `/layers/4/objects/2: missing field "rotation"`.
+pub fn at_pointer(pointer: &str, message: &str) -> String {
+    if message.starts_with('/') {
+        format!("{}{}", pointer, message)
+    } else {
+        format!("{}: {}", pointer, message)
+    }
+}
+
+/// The message of a JSON error. Errors produced while converting from a
+/// `JsonValue` have no location, so their meaningless "line 0 column 0"
+/// suffix is removed. Real locations are kept.
+pub fn json_error_message(e: &JsonError) -> String {
+    let message = e.to_string();
+    let suffix = " at line 0 column 0";
+    if message.ends_with(suffix) {
+        message[..message.len() - suffix.len()].to_string()
+    } else {
+        message
+    }
+}
+
+/// The error for the value at `pointer` failing to load with `e`. The
+/// message may start with the pointer of the field inside the value which
+/// was invalid, which is moved onto `pointer`. The line and column are left
+/// for `TiledError::locate` to fill in.
+pub fn pointer_error(pointer: &str, e: &JsonError) -> TiledError {
+    let message = json_error_message(e);
+    let (field, message) = match message.find(": ") {
+        Some(end) if message.starts_with('/') => (message[..end].to_string(), message[end + 2..].to_string()),
+        _ => (String::new(), message),
+    };
+    TiledError::InvalidValue { pointer: format!("{}{}", pointer, field), line: 0, column: 0, message }
+}
+
+/// Deserializes a `T` from `value`. If that fails and `value` is an object,
+/// the field which was invalid is found by trying without each one in turn
+/// (the one whose removal changes the error), and the message starts with
+/// its pointer, e.g. `/rotation: invalid type: string "left", expected f32`.
+pub(crate) fn from_value_at_field<T: DeserializeOwned>(value: &JsonValue) -> Result<T, String> {
+    let message = match T::deserialize(value) {
+        Ok(loaded) => return Ok(loaded),
+        Err(e) => json_error_message(&e),
+    };
+    // Removing any field would make it the missing one instead
+    let fields = match *value {
+        JsonValue::Object(ref fields) if !message.starts_with('/') && !message.starts_with("missing field") => fields,
+        _ => return Err(message),
+    };
+    
+    for key in fields.keys() {
+        let mut without = fields.clone();
+        without.remove(key);
+        let blamed = match T::deserialize(&JsonValue::Object(without)) {
+            Ok(_) => true,
+            Err(e) => json_error_message(&e) != message,
+        };
+        if blamed {
+            let pointer = format!("/{}", key.replace('~', "~0").replace('/', "~1"));
+            return Err(at_pointer(&pointer, &message));
+        }
+    }
+    Err(message)
+}
+
+/// The line and column, counting from 1, where the value at `pointer` starts
+/// in `json`, or None if there is no such value. The column counts bytes.
+pub(crate) fn pointer_position(json: &[u8], pointer: &str) -> Option<(usize, usize)> {
+    let mut pos = skip_whitespace(json, 0);
+    for part in pointer.split('/').skip(1) {
+        let part = part.replace("~1", "/").replace("~0", "~");
+        match *json.get(pos)? {
+            b'{' => loop {
+                pos = skip_whitespace(json, pos + 1);
+                let end = skip_value(json, pos)?;
+                let key: String = ::serde_json::from_slice(&json[pos..end]).ok()?;
+                pos = skip_whitespace(json, end);
+                if *json.get(pos)? != b':' {
+                    return None;
+                }
+                pos = skip_whitespace(json, pos + 1);
+                if key == part {
+                    break;
+                }
+                pos = skip_whitespace(json, skip_value(json, pos)?);
+                if *json.get(pos)? != b',' {
+                    return None;
+                }
+            },
+            b'[' => {
+                let index: usize = part.parse().ok()?;
+                pos = skip_whitespace(json, pos + 1);
+                for _ in 0..index {
+                    pos = skip_whitespace(json, skip_value(json, pos)?);
+                    if *json.get(pos)? != b',' {
+                        return None;
+                    }
+                    pos = skip_whitespace(json, pos + 1);
+                }
+                if matches!(json.get(pos), None | Some(&b']')) {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    
+    let line_start = json[..pos].iter().rposition(|&byte| byte == b'\n').map_or(0, |i| i + 1);
+    let line = json[..pos].iter().filter(|&&byte| byte == b'\n').count() + 1;
+    Some((line, pos - line_start + 1))
+}
+
+fn skip_whitespace(json: &[u8], pos: usize) -> usize {
+    pos + json[pos.min(json.len())..].iter().take_while(|byte| byte.is_ascii_whitespace()).count()
+}
+
+/// The index just past the JSON value which starts at `pos`
+fn skip_value(json: &[u8], pos: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, &byte) in json.iter().enumerate().skip(pos) {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == b'"' {
+                in_string = false;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' | b',' | b':' if depth == 0 => return Some(i),
+            b']' | b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ if depth == 0 && byte.is_ascii_whitespace() => return Some(i),
+            _ => {}
+        }
+    }
+    if depth == 0 && !in_string { Some(json.len()) } else { None }
+}
@@ -0,0 +1,262 @@
+//! Working out which version of Tiled's JSON format a map was saved in, and
+//! which optional parts of the format it uses.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use serde_json::Value as JsonValue;
+use serde_json::Error as JsonError;
+
+/// A part of Tiled's JSON format which only some maps use, usually because
+/// it was added or replaced in a particular version of Tiled
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FormatFeature {
+    /// Properties written as a list of `{"name", "type", "value"}` objects,
+    /// from Tiled 1.2
+    TypedProperties,
+    /// `class` written instead of `type`, from Tiled 1.9
+    ClassField,
+    /// Tile layer data written as one CSV string
+    CsvStringData,
+    /// Tile layer data encoded as base64 or compressed
+    EncodedData,
+    /// Infinite maps, whose tile layers are stored in chunks
+    InfiniteMap,
+    /// Layers which contain other layers
+    GroupLayers,
+    /// Layers showing a single image
+    ImageLayers,
+    /// Objects which take their settings from a template file
+    Templates,
+    /// Text objects
+    TextObjects,
+    /// Objects which are open lines rather than closed polygons
+    Polylines,
+    /// Terrain information, which Tiled 1.5 replaced with Wang sets
+    Terrains,
+    /// Wang sets, from Tiled 1.1
+    WangSets,
+    /// A tileset's `tiles` written as a list instead of an object keyed by
+    /// tile id, from Tiled 1.2
+    TileList,
+}
+
+/// How well this crate handles a `FormatFeature`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Support {
+    /// Loaded in full
+    Supported,
+    /// The map loads, but this part of it is left out
+    Ignored,
+    /// Maps using it fail to load
+    Unsupported,
+}
+
+impl FormatFeature {
+    pub fn support(self) -> Support {
+        match self {
+            FormatFeature::TypedProperties |
+            FormatFeature::ClassField |
+            FormatFeature::CsvStringData |
+            FormatFeature::ImageLayers |
+            FormatFeature::Terrains => Support::Supported,
+            FormatFeature::TextObjects |
+            FormatFeature::Polylines |
+            FormatFeature::WangSets => Support::Ignored,
+            FormatFeature::EncodedData |
+            FormatFeature::InfiniteMap |
+            FormatFeature::GroupLayers |
+            FormatFeature::Templates |
+            FormatFeature::TileList => Support::Unsupported,
+        }
+    }
+    
+    /// A short description for messages, e.g. "group layers"
+    pub fn name(self) -> &'static str {
+        match self {
+            FormatFeature::TypedProperties => "typed properties",
+            FormatFeature::ClassField => "class fields",
+            FormatFeature::CsvStringData => "CSV string tile data",
+            FormatFeature::EncodedData => "base64 or compressed tile data",
+            FormatFeature::InfiniteMap => "infinite maps",
+            FormatFeature::GroupLayers => "group layers",
+            FormatFeature::ImageLayers => "image layers",
+            FormatFeature::Templates => "object templates",
+            FormatFeature::TextObjects => "text objects",
+            FormatFeature::Polylines => "polylines",
+            FormatFeature::Terrains => "terrains",
+            FormatFeature::WangSets => "Wang sets",
+            FormatFeature::TileList => "tile lists",
+        }
+    }
+}
+
+impl fmt::Display for FormatFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a level's JSON says about the version of Tiled which saved it, and the
+/// optional parts of the format it uses. Tilesets in their own files aren't
+/// included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormatProfile {
+    /// The version of the JSON format, e.g. `"1.10"`. Older versions of
+    /// Tiled wrote this as a number.
+    pub version: Option<String>,
+    /// The version of Tiled which saved the map, written since Tiled 1.0.1
+    pub tiled_version: Option<String>,
+    pub features: BTreeSet<FormatFeature>,
+}
+
+impl FormatProfile {
+    /// Inspects a level's JSON without loading it
+    pub fn detect(level: &JsonValue) -> FormatProfile {
+        let version = |key| match level.get(key) {
+            Some(JsonValue::String(version)) => Some(version.clone()),
+            Some(JsonValue::Number(n)) => Some(n.to_string()),
+            _ => None,
+        };
+        
+        let mut profile = FormatProfile {
+            version: version("version"),
+            tiled_version: version("tiledversion"),
+            features: BTreeSet::new(),
+        };
+        profile.properties(level);
+        if level.get("infinite").and_then(JsonValue::as_bool) == Some(true) {
+            profile.features.insert(FormatFeature::InfiniteMap);
+        }
+        if let Some(JsonValue::Array(layers)) = level.get("layers") {
+            for layer in layers {
+                profile.layer(layer);
+            }
+        }
+        if let Some(JsonValue::Array(tilesets)) = level.get("tilesets") {
+            for tileset in tilesets {
+                profile.tileset(tileset);
+            }
+        }
+        profile
+    }
+    
+    pub fn uses(&self, feature: FormatFeature) -> bool {
+        self.features.contains(&feature)
+    }
+    
+    /// The features used which aren't loaded in full, such as for telling a
+    /// user which parts of their map a game doesn't support
+    pub fn unsupported_features(&self) -> Vec<FormatFeature> {
+        self.features.iter().cloned().filter(|feature| feature.support() != Support::Supported).collect()
+    }
+    
+    /// Fails, naming them, if any of the features used stop the map loading
+    pub(crate) fn check_loadable(&self) -> Result<(), JsonError> {
+        use serde::de::Error;
+        
+        let blocking: Vec<&str> = self.features.iter()
+            .filter(|feature| feature.support() == Support::Unsupported)
+            .map(|feature| feature.name())
+            .collect();
+        if blocking.is_empty() {
+            return Ok(());
+        }
+        
+        let saved_by = match self.tiled_version {
+            Some(ref version) => format!(" (saved by Tiled {})", version),
+            None => String::new(),
+        };
+        Err(JsonError::custom(format!("The map uses features which can't be loaded{}: {}", saved_by, blocking.join(", "))))
+    }
+    
+    fn properties(&mut self, value: &JsonValue) {
+        if let Some(&JsonValue::Array(_)) = value.get("properties") {
+            self.features.insert(FormatFeature::TypedProperties);
+        }
+        if value.get("class").is_some() {
+            self.features.insert(FormatFeature::ClassField);
+        }
+    }
+    
+    fn layer(&mut self, layer: &JsonValue) {
+        self.properties(layer);
+        match layer.get("type").and_then(JsonValue::as_str) {
+            Some("group") => { self.features.insert(FormatFeature::GroupLayers); }
+            Some("imagelayer") => { self.features.insert(FormatFeature::ImageLayers); }
+            _ => {}
+        }
+        
+        if layer.get("chunks").is_some() {
+            self.features.insert(FormatFeature::InfiniteMap);
+        }
+        let encoding = layer.get("encoding").and_then(JsonValue::as_str);
+        let compression = layer.get("compression").and_then(JsonValue::as_str);
+        if encoding == Some("base64") || compression.is_some_and(|c| !c.is_empty()) {
+            self.features.insert(FormatFeature::EncodedData);
+        } else if let Some(&JsonValue::String(_)) = layer.get("data") {
+            self.features.insert(FormatFeature::CsvStringData);
+        }
+        
+        if let Some(JsonValue::Array(objects)) = layer.get("objects") {
+            for object in objects {
+                self.object(object);
+            }
+        }
+        if let Some(JsonValue::Array(layers)) = layer.get("layers") {
+            for layer in layers {
+                self.layer(layer);
+            }
+        }
+    }
+    
+    fn object(&mut self, object: &JsonValue) {
+        self.properties(object);
+        for &(key, feature) in &[
+            ("template", FormatFeature::Templates),
+            ("text", FormatFeature::TextObjects),
+            ("polyline", FormatFeature::Polylines),
+        ] {
+            if object.get(key).is_some() {
+                self.features.insert(feature);
+            }
+        }
+    }
+    
+    fn tileset(&mut self, tileset: &JsonValue) {
+        self.properties(tileset);
+        if tileset.get("terrains").is_some() {
+            self.features.insert(FormatFeature::Terrains);
+        }
+        if tileset.get("wangsets").is_some() {
+            self.features.insert(FormatFeature::WangSets);
+        }
+        if let Some(&JsonValue::Array(_)) = tileset.get("tiles") {
+            self.features.insert(FormatFeature::TileList);
+        }
+    }
+}
+
+#[test]
+fn detect_features() {
+    use std::convert::TryFrom;
+    use serde_json;
+    use level::Level;
+    
+    let json = r#"{
+        "version": "1.10", "tiledversion": "1.10.2",
+        "height": 1, "width": 1, "tileheight": 16, "tilewidth": 16,
+        "orientation": "orthogonal", "renderorder": "right-down",
+        "properties": [{ "name": "music", "type": "file", "value": "theme.ogg" }],
+        "layers": [{ "type": "group", "name": "g", "layers": [{ "type": "imagelayer", "name": "sky" }] }],
+        "tilesets": [{ "firstgid": 1, "name": "t", "wangsets": [] }]
+    }"#;
+    let profile = FormatProfile::detect(&serde_json::from_str(json).unwrap());
+    assert_eq!(profile.version, Some("1.10".to_string()));
+    assert!(profile.uses(FormatFeature::TypedProperties));
+    assert_eq!(profile.unsupported_features(), vec![
+        FormatFeature::GroupLayers, FormatFeature::WangSets,
+    ]);
+    
+    let error = Level::try_from(json).unwrap_err().to_string();
+    assert!(error.starts_with("The map uses features which can't be loaded (saved by Tiled 1.10.2): group layers"));
+}
@@ -0,0 +1,72 @@
+//! Events emitted while loading levels and tilesets. With the `log` feature
+//! they are sent to the `log` crate, otherwise they compile to nothing and
+//! no timings are taken. With the `tracing` feature, loading is also split
+//! into `tracing` spans.
+
+/// Logs a debug event about loading
+#[cfg(feature = "log")]
+macro_rules! load_debug {
+    ($($arg:tt)*) => { debug!($($arg)*) }
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! load_debug {
+    // The arguments are type checked but never evaluated
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+/// Logs a trace event about loading
+#[cfg(feature = "log")]
+macro_rules! load_trace {
+    ($($arg:tt)*) => { trace!($($arg)*) }
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! load_trace {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+/// Enters a debug-level `tracing` span, which lasts until the returned guard
+/// is dropped. Takes the same arguments as `tracing::debug_span!`.
+#[cfg(feature = "tracing")]
+macro_rules! load_span {
+    ($($arg:tt)*) => { ::tracing::debug_span!($($arg)*).entered() }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! load_span {
+    // Field values may use tracing's `%` and `?` sigils, so they can't be
+    // type checked here
+    ($($arg:tt)*) => { () }
+}
+
+/// Measures how long a phase of loading took. Without the `log` feature this
+/// is empty and always reports 0.
+pub(crate) struct Timer {
+    #[cfg(feature = "log")]
+    start: ::std::time::Instant,
+}
+
+impl Timer {
+    #[cfg(feature = "log")]
+    pub(crate) fn start() -> Timer {
+        Timer { start: ::std::time::Instant::now() }
+    }
+    
+    #[cfg(not(feature = "log"))]
+    pub(crate) fn start() -> Timer {
+        Timer {}
+    }
+    
+    /// Milliseconds since the timer was started
+    #[cfg(feature = "log")]
+    pub(crate) fn elapsed_ms(&self) -> f64 {
+        let elapsed = self.start.elapsed();
+        elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1_000_000.0
+    }
+    
+    #[cfg(not(feature = "log"))]
+    pub(crate) fn elapsed_ms(&self) -> f64 {
+        0.0
+    }
+}
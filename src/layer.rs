@@ -1,13 +1,21 @@
-use std::collections::HashMap;
-use GlobalTile;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use {GlobalTile, LocalTile, TileCoord, TileFlip, Transform2D, tile_id_from_json};
+use error::{TiledError, at_pointer, from_value_at_field};
+use level::Level;
+use color::Color;
+use properties::Properties;
+use tileset::Tileset;
 use serde::{Deserialize, Deserializer};
 use serde_json::Value as JsonValue;
-use serde_json::from_value;
 
 #[derive(Clone, Debug)]
 pub enum Layer {
     Tiles(TileLayer),
     Objects(ObjectLayer),
+    Images(ImageLayer),
 }
 
 impl Layer {
@@ -15,15 +23,204 @@ impl Layer {
         match *self {
             Layer::Tiles(ref tiles) => &tiles.name,
             Layer::Objects(ref objects) => &objects.name,
+            Layer::Images(ref image) => &image.name,
         }
     }
+    
+    pub fn set_name<S: Into<String>>(&mut self, name: S) {
+        match *self {
+            Layer::Tiles(ref mut tiles) => tiles.name = name.into(),
+            Layer::Objects(ref mut objects) => objects.name = name.into(),
+            Layer::Images(ref mut image) => image.name = name.into(),
+        }
+    }
+    
+    /// Sets the name, for building layers in a chain of calls
+    pub fn with_name(mut self, name: &str) -> Layer {
+        self.set_name(name);
+        self
+    }
+    
+    /// A copy of this layer with a different name, e.g. for duplicating a
+    /// layer in an editor
+    pub fn clone_renamed(&self, new_name: &str) -> Layer {
+        self.clone().with_name(new_name)
+    }
+    
+    /// A copy of this layer with a different layer id. The objects of an
+    /// object layer keep their ids, so adding the copy to the same level
+    /// needs `Level::reassign_object_ids` afterwards.
+    pub fn clone_with_id(&self, new_id: u32) -> Layer {
+        let mut layer = self.clone();
+        match layer {
+            Layer::Tiles(ref mut tiles) => tiles.id = Some(new_id),
+            Layer::Objects(ref mut objects) => objects.id = Some(new_id),
+            Layer::Images(ref mut image) => image.id = Some(new_id),
+        }
+        layer
+    }
+    
+    /// Sets the opacity, from 0.0 (invisible) to 1.0 (opaque)
+    pub fn with_opacity(mut self, opacity: f32) -> Layer {
+        match self {
+            Layer::Tiles(ref mut tiles) => tiles.opacity = opacity,
+            Layer::Objects(ref mut objects) => objects.opacity = opacity,
+            Layer::Images(ref mut image) => image.opacity = opacity,
+        }
+        self
+    }
+    
+    /// Shows or hides the layer
+    pub fn with_visible(mut self, visible: bool) -> Layer {
+        match self {
+            Layer::Tiles(ref mut tiles) => tiles.visible = visible,
+            Layer::Objects(ref mut objects) => objects.visible = visible,
+            Layer::Images(ref mut image) => image.visible = visible,
+        }
+        self
+    }
+    
+    /// Sets the offset in pixels the layer is drawn at
+    pub fn with_offset(mut self, x: f32, y: f32) -> Layer {
+        match self {
+            Layer::Tiles(ref mut tiles) => {
+                tiles.offsetx = Some(x);
+                tiles.offsety = Some(y);
+            }
+            Layer::Objects(ref mut objects) => {
+                objects.offsetx = Some(x);
+                objects.offsety = Some(y);
+            }
+            Layer::Images(ref mut image) => {
+                image.offsetx = Some(x);
+                image.offsety = Some(y);
+            }
+        }
+        self
+    }
+    
+    /// The unique ID of this layer, if the map was saved by a version of Tiled
+    /// which assigns layer IDs
+    pub fn id(&self) -> Option<u32> {
+        match *self {
+            Layer::Tiles(ref tiles) => tiles.id,
+            Layer::Objects(ref objects) => objects.id,
+            Layer::Images(ref image) => image.id,
+        }
+    }
+    
+    /// The custom properties of the layer itself
+    pub fn properties(&self) -> &Properties {
+        match *self {
+            Layer::Tiles(ref tiles) => &tiles.properties,
+            Layer::Objects(ref objects) => &objects.properties,
+            Layer::Images(ref image) => &image.properties,
+        }
+    }
+    
+    /// Whether the layer is shown in the editor
+    pub fn visible(&self) -> bool {
+        match *self {
+            Layer::Tiles(ref tiles) => tiles.visible,
+            Layer::Objects(ref objects) => objects.visible,
+            Layer::Images(ref image) => image.visible,
+        }
+    }
+    
+    /// Whether the layer is locked against editing in Tiled, defaulting to
+    /// false
+    pub fn locked(&self) -> bool {
+        let locked = match *self {
+            Layer::Tiles(ref tiles) => tiles.locked,
+            Layer::Objects(ref objects) => objects.locked,
+            Layer::Images(ref image) => image.locked,
+        };
+        locked.unwrap_or(false)
+    }
+    
+    /// Where in the level's JSON this layer was loaded from, e.g.
+    /// `/layers/3`, if the level was loaded with
+    /// `LoadOptions::record_source_pointers`. This doesn't change when layers
+    /// are added, removed or moved after loading, so it can be used to patch
+    /// the original file with `Level::apply_json_patch`.
+    pub fn source_pointer(&self) -> Option<String> {
+        self.source_index().map(|index| format!("/layers/{}", index))
+    }
+    
+    pub(crate) fn source_index(&self) -> Option<usize> {
+        match *self {
+            Layer::Tiles(ref tiles) => tiles.source_index,
+            Layer::Objects(ref objects) => objects.source_index,
+            Layer::Images(ref image) => image.source_index,
+        }
+    }
+    
+    /// Records that this layer, and any objects in it, were loaded from
+    /// index `index` of the level's layers
+    pub(crate) fn record_source(&mut self, index: usize) {
+        match *self {
+            Layer::Tiles(ref mut tiles) => tiles.source_index = Some(index),
+            Layer::Objects(ref mut objects) => {
+                objects.source_index = Some(index);
+                for (i, object) in objects.objects.iter_mut().enumerate() {
+                    object.source_index = Some((index, i));
+                }
+            }
+            Layer::Images(ref mut image) => image.source_index = Some(index),
+        }
+    }
+    
+    /// The offset in pixels the layer is drawn at, defaulting to (0, 0)
+    pub fn offset(&self) -> (f32, f32) {
+        let (x, y) = match *self {
+            Layer::Tiles(ref tiles) => (tiles.offsetx, tiles.offsety),
+            Layer::Objects(ref objects) => (objects.offsetx, objects.offsety),
+            Layer::Images(ref image) => (image.offsetx, image.offsety),
+        };
+        (x.unwrap_or(0.0), y.unwrap_or(0.0))
+    }
+    
+    /// The parallax scrolling factor of this layer, defaulting to 1.0
+    /// (scrolling with the camera) along each axis
+    pub fn parallax(&self) -> (f32, f32) {
+        let (x, y) = match *self {
+            Layer::Tiles(ref tiles) => (tiles.parallaxx, tiles.parallaxy),
+            Layer::Objects(ref objects) => (objects.parallaxx, objects.parallaxy),
+            Layer::Images(ref image) => (image.parallaxx, image.parallaxy),
+        };
+        (x.unwrap_or(1.0), y.unwrap_or(1.0))
+    }
+}
+
+impl Default for TileLayer {
+    fn default() -> TileLayer {
+        TileLayer::EMPTY
+    }
+}
+
+impl From<TileLayer> for Layer {
+    fn from(layer: TileLayer) -> Layer {
+        Layer::Tiles(layer)
+    }
+}
+
+impl From<ObjectLayer> for Layer {
+    fn from(layer: ObjectLayer) -> Layer {
+        Layer::Objects(layer)
+    }
 }
 
-impl Deserialize for Layer {
-    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
-        use serde::de::Error as SerdeError;
-        use std::error::Error;
-        let data = try!(JsonValue::deserialize(d));
+impl From<ImageLayer> for Layer {
+    fn from(layer: ImageLayer) -> Layer {
+        Layer::Images(layer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Layer {
+    #[allow(clippy::needless_borrowed_reference)]
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let data = JsonValue::deserialize(d)?;
         let kind = match data {
             JsonValue::Object(ref data) => match data.get("type") {
                 Some(&JsonValue::String(ref kind)) => kind.clone(),
@@ -33,19 +230,15 @@ impl Deserialize for Layer {
         };
         
         Ok(match &kind[..] {
-            "tilelayer" => Layer::Tiles(match from_value(data) {
-                Ok(layer) => layer,
-                Err(e) => return Err(D::Error::custom(
-                    Into::<String>::into("tilelayer failed ") + e.description()
-                )),
-            }),
-            "objectgroup" => Layer::Objects(match from_value(data) {
-                Ok(layer) => layer,
-                Err(e) => return Err(D::Error::custom(
-                    Into::<String>::into("objectgroup failed ") + e.description()
-                )),
-            }),
-            _ => return Err(D::Error::custom("Unknown layer type")),
+            // Errors start with the JSON pointer of the value inside the
+            // layer which failed, where it can be found
+            "tilelayer" if data.get("encoding").and_then(JsonValue::as_str) == Some("base64") => {
+                return Err(D::Error::custom("Base64 encoded layer data is not supported"));
+            }
+            "tilelayer" => Layer::Tiles(from_value_at_field(&data).map_err(D::Error::custom)?),
+            "objectgroup" => Layer::Objects(from_value_at_field(&data).map_err(D::Error::custom)?),
+            "imagelayer" => Layer::Images(from_value_at_field(&data).map_err(D::Error::custom)?),
+            _ => return Err(D::Error::custom(format!("Unknown layer type `{}`", kind))),
         })
     }
 }
@@ -53,44 +246,785 @@ impl Deserialize for Layer {
 #[derive(Clone, Debug, Deserialize)]
 pub struct TileLayer {
     pub name: String,
+    /// Unique ID of the layer, written by Tiled 1.2 and newer
+    pub id: Option<u32>,
     pub opacity: f32,
-    pub properties: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub properties: Properties,
     pub visible: bool,
     pub width: u32,
     pub height: u32,
     pub x: f32,
     pub y: f32,
+    pub offsetx: Option<f32>,
+    pub offsety: Option<f32>,
+    pub parallaxx: Option<f32>,
+    pub parallaxy: Option<f32>,
+    /// Whether the layer is locked against editing in Tiled
+    pub locked: Option<bool>,
+    /// The index in the level's `layers` this layer was loaded from, when
+    /// `LoadOptions::record_source_pointers` is set. See
+    /// `Layer::source_pointer`.
+    #[serde(skip_deserializing)]
+    pub source_index: Option<usize>,
     
+    #[serde(deserialize_with = "deserialize_tile_data")]
     pub data: Vec<GlobalTile>,
 }
 
+impl TileLayer {
+    /// A visible, fully opaque 0x0 layer with no name and no data. Accessors
+    /// return None or empty iterators for it rather than panicking.
+    pub const EMPTY: TileLayer = TileLayer {
+        name: String::new(),
+        id: None,
+        opacity: 1.0,
+        properties: Properties::new(),
+        visible: true,
+        width: 0,
+        height: 0,
+        x: 0.0,
+        y: 0.0,
+        offsetx: None,
+        offsety: None,
+        parallaxx: None,
+        parallaxy: None,
+        locked: None,
+        source_index: None,
+        
+        data: Vec::new(),
+    };
+    
+    /// A visible, fully opaque layer of the given size, with the tile at each
+    /// position given by `f(x, y)`. Useful for procedural generation.
+    pub fn generate_from<F: Fn(u32, u32) -> GlobalTile>(name: &str, width: u32, height: u32, f: F) -> TileLayer {
+        let mut data = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+        
+        TileLayer {
+            name: name.to_string(),
+            width,
+            height,
+            data,
+            ..TileLayer::EMPTY
+        }
+    }
+    
+    /// Wraps this layer in a `Layer`, e.g. for building a level's layer list.
+    /// There is no by-reference equivalent since a `Layer` owns its contents;
+    /// match on `Layer::Tiles` to borrow one back out.
+    pub fn to_layer(self) -> Layer {
+        Layer::Tiles(self)
+    }
+    
+    /// Sets a custom property, for building layers in a chain of calls
+    pub fn with_property<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> TileLayer {
+        self.properties.set(key, value);
+        self
+    }
+    
+    /// Removes a custom property, for building layers in a chain of calls
+    pub fn without_property(mut self, key: &str) -> TileLayer {
+        self.properties.unset(key);
+        self
+    }
+    
+    /// Finds the tile of this layer under a position in world pixels, taking
+    /// the layer's offset into account. Returns None when the position is
+    /// outside of the layer. The orientation math is done by
+    /// `Level::pixel_to_tile`.
+    pub fn world_to_tile(&self, level: &Level, x: f32, y: f32) -> Option<TileCoord> {
+        let (offset_x, offset_y) = (self.offsetx.unwrap_or(0.0), self.offsety.unwrap_or(0.0));
+        match level.pixel_to_tile(x - offset_x, y - offset_y) {
+            Some(pos) if pos.x < self.width && pos.y < self.height => Some(pos),
+            _ => None,
+        }
+    }
+    
+    /// Like `world_to_tile`, but also accounts for the layer's parallax
+    /// scrolling while the view is centered on the given point
+    pub fn world_to_tile_parallax(&self, level: &Level, x: f32, y: f32, view_center_x: f32, view_center_y: f32) -> Option<TileCoord> {
+        let (shift_x, shift_y) = level.parallax_shift(self.parallax(), view_center_x, view_center_y);
+        self.world_to_tile(level, x - shift_x, y - shift_y)
+    }
+    
+    /// The position in world pixels of a tile's origin in this layer, taking
+    /// the layer's offset into account. See `Level::tile_to_pixel`.
+    pub fn tile_to_world(&self, level: &Level, x: u32, y: u32) -> (f32, f32) {
+        let (px, py) = level.tile_to_pixel(x, y);
+        (px + self.offsetx.unwrap_or(0.0), py + self.offsety.unwrap_or(0.0))
+    }
+    
+    /// The parallax scrolling factor of this layer, defaulting to 1.0
+    /// along each axis
+    pub fn parallax(&self) -> (f32, f32) {
+        (self.parallaxx.unwrap_or(1.0), self.parallaxy.unwrap_or(1.0))
+    }
+    
+    /// The tile at the given position, or None if it is outside of the layer
+    pub fn tile_at(&self, x: u32, y: u32) -> Option<GlobalTile> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.data.get(y as usize * self.width as usize + x as usize).cloned()
+    }
+    
+    /// The tiles of row `y`, left to right, or None if there is no such row
+    pub fn row(&self, y: u32) -> Option<&[GlobalTile]> {
+        if y >= self.height {
+            return None;
+        }
+        let start = y as usize * self.width as usize;
+        self.data.get(start..start + self.width as usize)
+    }
+    
+    /// Iterates the rows of the layer from top to bottom, each one `width`
+    /// tiles long
+    pub fn iter_rows<'a>(&'a self) -> impl Iterator<Item = &'a [GlobalTile]> + 'a {
+        (0..self.height).filter_map(move |y| self.row(y))
+    }
+    
+    /// Grows the layer to the given size, filling new cells with `fill`.
+    /// Existing tiles keep their positions. Fails if either dimension would
+    /// get smaller; use `shrink` for that.
+    pub fn expand(&mut self, new_width: u32, new_height: u32, fill: GlobalTile) -> Result<(), TiledError> {
+        if new_width < self.width || new_height < self.height {
+            return Err(self.resize_error(new_width, new_height));
+        }
+        self.resize(new_width, new_height, fill);
+        Ok(())
+    }
+    
+    /// Crops the layer to the given size, keeping the top-left tiles. Fails if
+    /// either dimension would get larger; use `expand` for that.
+    pub fn shrink(&mut self, new_width: u32, new_height: u32) -> Result<(), TiledError> {
+        if new_width > self.width || new_height > self.height {
+            return Err(self.resize_error(new_width, new_height));
+        }
+        self.resize(new_width, new_height, GlobalTile::EMPTY);
+        Ok(())
+    }
+    
+    fn resize_error(&self, new_width: u32, new_height: u32) -> TiledError {
+        TiledError::InvalidResize {
+            from: (self.width, self.height),
+            to: (new_width, new_height),
+        }
+    }
+    
+    /// Rebuilds the data at a new size, keeping tiles at the same positions
+    /// and filling any new cells with `fill`
+    fn resize(&mut self, new_width: u32, new_height: u32, fill: GlobalTile) {
+        let mut data = Vec::with_capacity(new_width as usize * new_height as usize);
+        for y in 0..new_height {
+            for x in 0..new_width {
+                data.push(self.tile_at(x, y).unwrap_or(fill));
+            }
+        }
+        self.data = data;
+        self.width = new_width;
+        self.height = new_height;
+    }
+    
+    /// Mirrors the layer left to right. Each tile's horizontal flip flag is
+    /// toggled, so the tiles are mirrored as well as moved.
+    pub fn flip_horizontal(&mut self) {
+        if self.width == 0 {
+            return;
+        }
+        for row in self.data.chunks_mut(self.width as usize) {
+            row.reverse();
+            for tile in row {
+                *tile = toggle_flip(*tile, |flip| flip.horizontal = !flip.horizontal);
+            }
+        }
+    }
+    
+    /// Mirrors the layer top to bottom. Each tile's vertical flip flag is
+    /// toggled, so the tiles are mirrored as well as moved.
+    pub fn flip_vertical(&mut self) {
+        if self.width == 0 {
+            return;
+        }
+        let mut data = Vec::with_capacity(self.data.len());
+        for row in self.data.chunks(self.width as usize).rev() {
+            data.extend(row.iter().map(|&tile| toggle_flip(tile, |flip| flip.vertical = !flip.vertical)));
+        }
+        self.data = data;
+    }
+    
+    /// Iterates the tiles on the edges of the layer, going clockwise from the
+    /// top-left: the top row left to right, the right column top to bottom,
+    /// the bottom row right to left, then the left column bottom to top.
+    /// Each tile is only visited once.
+    pub fn border_tiles<'a>(&'a self) -> impl Iterator<Item = ((u32, u32), GlobalTile)> + 'a {
+        let (w, h) = if self.width == 0 || self.height == 0 {
+            (0, 0)
+        } else {
+            (self.width, self.height)
+        };
+        
+        // The bottom row and left column only exist separately from the top
+        // row and right column when the layer is at least 2 tiles in that axis
+        let bottom_end = if h > 1 { w - 1 } else { 0 };
+        let left_end = if w > 1 { h - 1 } else { 1 };
+        
+        let top = (0..w).map(|x| (x, 0));
+        let right = (1..h).map(move |y| (w - 1, y));
+        let bottom = (0..bottom_end).rev().map(move |x| (x, h - 1));
+        let left = (1..left_end).rev().map(|y| (0, y));
+        
+        top.chain(right).chain(bottom).chain(left)
+            .filter_map(move |(x, y)| self.tile_at(x, y).map(|tile| ((x, y), tile)))
+    }
+    
+    /// Indices into `tilesets` of the tilesets this layer uses, in ascending
+    /// order. Gids which don't belong to any tileset are skipped.
+    pub fn referenced_tilesets<S: Borrow<Tileset>>(&self, tilesets: &[S]) -> Vec<usize> {
+        referenced_tilesets(self.data.iter().cloned(), tilesets)
+    }
+    
+    /// Combines this layer with another of the same size cell by cell, giving
+    /// a layer with this one's settings and `f(this tile, other tile)` in each
+    /// cell. Fails with `DimensionMismatch` if the layers differ in size.
+    pub fn zip_with<F>(&self, other: &TileLayer, f: F) -> Result<TileLayer, TiledError>
+        where F: Fn(GlobalTile, GlobalTile) -> GlobalTile
+    {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(TiledError::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (other.width, other.height),
+            });
+        }
+        
+        let mut combined = self.clone();
+        for (tile, &other) in combined.data.iter_mut().zip(&other.data) {
+            *tile = f(*tile, other);
+        }
+        Ok(combined)
+    }
+    
+    /// Moves the tiles of the layer by `transform`, which works in tiles with
+    /// (0, 0) at the top-left corner of the layer. The layer keeps its size:
+    /// each cell takes the tile which `transform` moves onto its center
+    /// (nearest neighbor), and cells nothing lands on are left empty. For a
+    /// quarter turn or a mirror, the flip flags of the tiles are updated too
+    /// so the tiles themselves are turned; otherwise they are left alone.
+    ///
+    /// For example, to rotate an `n`x`n` layer a quarter turn clockwise use
+    /// `matrix: [[0.0, -1.0], [1.0, 0.0]]` and `translation: [n, 0.0]`.
+    ///
+    /// Fails with `NonInvertibleTransform` if the transform can't be undone,
+    /// e.g. because it scales by 0, leaving the layer unchanged.
+    pub fn apply_transform(&mut self, transform: &Transform2D) -> Result<(), TiledError> {
+        let inverse = match transform.inverse() {
+            Some(inverse) => inverse,
+            None => return Err(TiledError::NonInvertibleTransform),
+        };
+        let flip = |gid: GlobalTile| match TileFlip::from_gid(gid).transformed(&transform.matrix) {
+            Some(flip) if !gid.is_empty() => flip.apply_to(gid),
+            _ => gid,
+        };
+        
+        let mut data = Vec::with_capacity(self.data.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let source = inverse.apply([x as f32 + 0.5, y as f32 + 0.5]);
+                let (sx, sy) = (source[0].floor(), source[1].floor());
+                let inside = sx >= 0.0 && sy >= 0.0 && sx < self.width as f32 && sy < self.height as f32;
+                data.push(match self.tile_at(sx as u32, sy as u32) {
+                    Some(tile) if inside => flip(tile),
+                    _ => GlobalTile::EMPTY,
+                });
+            }
+        }
+        self.data = data;
+        Ok(())
+    }
+    
+    /// A copy of this layer where each cell is `GlobalTile(1)` if `f` accepts
+    /// its tile, or empty otherwise. Handy for marking regions such as all
+    /// water tiles for physics or pathfinding.
+    pub fn mask_from_predicate<F: Fn(GlobalTile) -> bool>(&self, f: F) -> TileLayer {
+        let mut mask = self.clone();
+        for tile in &mut mask.data {
+            *tile = if f(*tile) { GlobalTile(1) } else { GlobalTile::EMPTY };
+        }
+        mask
+    }
+    
+    /// A copy of this layer with tiles swapped through `palette`, e.g. to turn
+    /// a desert map into a snowy one. The palette is keyed by canonical gid,
+    /// so a tile is swapped however it's flipped, and the cell keeps its flip
+    /// flags in place of any on the replacement. Tiles not in the palette are
+    /// copied unchanged.
+    pub fn apply_palette(&self, palette: &HashMap<GlobalTile, GlobalTile>) -> TileLayer {
+        let mut swapped = self.clone();
+        for tile in &mut swapped.data {
+            if let Some(&replacement) = palette.get(&tile.canonical()) {
+                *tile = TileFlip::from_gid(*tile).apply_to(replacement);
+            }
+        }
+        swapped
+    }
+    
+    /// Fills each cell which is non-empty in `mask`, such as one made by
+    /// `mask_from_predicate`, with one of `candidates` from `tileset` picked by
+    /// `Tileset::weighted_pick`. `rolls` gives a random number in `0..1` for
+    /// each cell filled, so any RNG can be used. Nothing changes if there are
+    /// no candidates. Fails with `DimensionMismatch` if the mask is a
+    /// different size to this layer, or `TileNotFound` if a candidate isn't
+    /// a tile of `tileset`, without changing anything.
+    pub fn scatter<F>(&mut self, mask: &TileLayer, tileset: &Tileset, candidates: &[LocalTile], mut rolls: F) -> Result<(), TiledError>
+        where F: FnMut() -> f32
+    {
+        if (self.width, self.height) != (mask.width, mask.height) {
+            return Err(TiledError::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (mask.width, mask.height),
+            });
+        }
+        for &local in candidates {
+            // A gid which overflows into the flip flags would be another tile
+            let gid = tileset.firstgid.0.checked_add(local.0).map(GlobalTile);
+            match gid {
+                Some(gid) if gid.strip_flags() == gid && tileset.contains_tile(gid) => {}
+                _ => return Err(TiledError::TileNotFound { tileset: tileset.name.clone(), tile: local.0 }),
+            }
+        }
+        if candidates.is_empty() {
+            return Ok(());
+        }
+        
+        for (tile, masked) in self.data.iter_mut().zip(&mask.data) {
+            if masked.is_empty() {
+                continue;
+            }
+            if let Some(local) = tileset.weighted_pick(candidates, rolls()) {
+                // Checked above
+                *tile = GlobalTile(tileset.firstgid.0 + local.0);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Iterates the cells which use a tile from `tileset`, row by row, giving
+    /// the position, the tile within the set and how it is flipped. The gid
+    /// range is worked out once, so this is cheaper than `find_local` on every
+    /// cell.
+    pub fn iter_tiles_of<'a>(&'a self, tileset: &Tileset) -> impl Iterator<Item = (u32, u32, LocalTile, TileFlip)> + 'a {
+        let first = tileset.firstgid.0;
+        let count = tileset.tilecount;
+        let width = self.width;
+        
+        self.data.iter().enumerate().filter_map(move |(i, &gid)| {
+            let id = gid.strip_flags().0;
+            if id == 0 || id < first || id - first >= count || width == 0 {
+                return None;
+            }
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            Some((x, y, LocalTile(id - first), TileFlip::from_gid(gid)))
+        })
+    }
+    
+    /// Iterates the tiles which aren't on the edges of the layer, row by row.
+    /// Layers which are 2 or fewer tiles wide or tall have no interior.
+    pub fn interior_tiles<'a>(&'a self) -> impl Iterator<Item = ((u32, u32), GlobalTile)> + 'a {
+        let (w, h) = (self.width, self.height);
+        let (end_x, end_y) = if w > 2 && h > 2 { (w - 1, h - 1) } else { (1, 1) };
+        
+        (1..end_y).flat_map(move |y| (1..end_x).map(move |x| (x, y)))
+            .filter_map(move |(x, y)| self.tile_at(x, y).map(|tile| ((x, y), tile)))
+    }
+}
+
+/// Deserializes tile layer data, including the index of the element which was
+/// invalid in the error so that it can be found in the map. Besides an array,
+/// the data can be a string of comma separated ids, as written with
+/// `"encoding": "csv"`.
+fn deserialize_tile_data<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<GlobalTile>, D::Error> {
+    use serde::de::Error;
+    
+    let values = match JsonValue::deserialize(d)? {
+        JsonValue::Array(values) => values,
+        JsonValue::String(csv) => return parse_csv_data(&csv).map_err(D::Error::custom),
+        _ => return Err(D::Error::custom("/data: expected an array or a string of comma separated tile ids")),
+    };
+    
+    let mut data = Vec::with_capacity(values.len());
+    for (i, value) in values.iter().enumerate() {
+        match tile_id_from_json(value) {
+            Ok(id) => data.push(GlobalTile(id)),
+            Err(e) => return Err(D::Error::custom(at_pointer(&format!("/data/{}", i), &e))),
+        }
+    }
+    Ok(data)
+}
+
+/// Parses CSV tile data. Whitespace around ids and a trailing comma or newline
+/// are allowed. Errors give the index of the first id which isn't valid.
+fn parse_csv_data(csv: &str) -> Result<Vec<GlobalTile>, String> {
+    let csv = csv.trim().trim_end_matches(',');
+    if csv.is_empty() {
+        return Ok(Vec::new());
+    }
+    
+    let mut data = Vec::new();
+    for (i, token) in csv.split(',').enumerate() {
+        match token.trim().parse() {
+            Ok(id) => data.push(GlobalTile(id)),
+            Err(_) => return Err(at_pointer(
+                &format!("/data/{}", i), &format!("`{}` is not a tile id", token.trim())
+            )),
+        }
+    }
+    Ok(data)
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ObjectLayer {
     pub name: String,
+    /// Unique ID of the layer, written by Tiled 1.2 and newer
+    pub id: Option<u32>,
     pub opacity: f32,
-    pub properties: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub properties: Properties,
     pub visible: bool,
     pub width: u32,
     pub height: u32,
     pub x: f32,
     pub y: f32,
+    pub offsetx: Option<f32>,
+    pub offsety: Option<f32>,
+    pub parallaxx: Option<f32>,
+    pub parallaxy: Option<f32>,
+    /// Whether the layer is locked against editing in Tiled
+    pub locked: Option<bool>,
+    /// The index in the level's `layers` this layer was loaded from, when
+    /// `LoadOptions::record_source_pointers` is set. See
+    /// `Layer::source_pointer`.
+    #[serde(skip_deserializing)]
+    pub source_index: Option<usize>,
     
+    /// Color used by the editor to draw the objects in this layer
+    pub color: Option<Color>,
     pub draworder: String,
+    #[serde(deserialize_with = "deserialize_objects")]
     pub objects: Vec<Object>,
 }
 
+impl ObjectLayer {
+    /// Wraps this layer in a `Layer`, e.g. for building a level's layer list
+    pub fn to_layer(self) -> Layer {
+        Layer::Objects(self)
+    }
+    
+    /// Sets a custom property, for building layers in a chain of calls
+    pub fn with_property<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> ObjectLayer {
+        self.properties.set(key, value);
+        self
+    }
+    
+    /// Removes a custom property, for building layers in a chain of calls
+    pub fn without_property(mut self, key: &str) -> ObjectLayer {
+        self.properties.unset(key);
+        self
+    }
+    
+    /// The color Tiled draws objects with when a layer has no `color`
+    pub const DEFAULT_COLOR: Color = Color { r: 0xA0, g: 0xA0, b: 0xA4, a: 0xFF };
+    
+    /// The color the editor draws this layer's objects with, falling back to
+    /// `DEFAULT_COLOR` like Tiled does. Useful for debug overlays which should
+    /// match the editor.
+    pub fn display_color(&self) -> Color {
+        self.color.unwrap_or(ObjectLayer::DEFAULT_COLOR)
+    }
+    
+    /// The order this layer's objects are drawn in, from its `draworder`.
+    /// Tiled uses `"topdown"` unless the layer says `"index"`.
+    pub fn draw_order(&self) -> ObjectOrder {
+        match &self.draworder[..] {
+            "index" => ObjectOrder::Index,
+            _ => ObjectOrder::TopDown,
+        }
+    }
+    
+    /// The objects in this layer in the given order, without changing the
+    /// order of the layer itself. `ObjectOrder::DrawOrder` gives them in the
+    /// order Tiled draws them, which is what functions returning several
+    /// objects use unless they take an order.
+    pub fn objects_in_order(&self, order: ObjectOrder) -> Vec<&Object> {
+        let mut objects: Vec<&Object> = self.objects.iter().collect();
+        let order = match order {
+            ObjectOrder::DrawOrder => self.draw_order(),
+            order => order,
+        };
+        if order == ObjectOrder::TopDown {
+            objects.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap_or(Ordering::Equal));
+        }
+        objects
+    }
+    
+    /// The objects in this layer ordered by ascending y position, without
+    /// changing the order of the layer itself. Objects with equal y keep their
+    /// relative order.
+    pub fn objects_sorted_by_y(&self) -> Vec<&Object> {
+        self.objects_in_order(ObjectOrder::TopDown)
+    }
+    
+    /// The objects in this layer ordered by ascending id, without changing the
+    /// order of the layer itself
+    pub fn objects_sorted_by_id(&self) -> Vec<&Object> {
+        let mut objects: Vec<&Object> = self.objects.iter().collect();
+        objects.sort_by_key(|object| object.id);
+        objects
+    }
+    
+    /// How many objects of each type the layer has, e.g. for checking that a
+    /// level has exactly one spawn point
+    pub fn count_by_type(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for object in &self.objects {
+            *counts.entry(object.kind.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+    
+    /// The objects in this layer grouped by type, each group in the layer's
+    /// draw order. Objects with no type are grouped under the empty string.
+    pub fn objects_by_type(&self) -> HashMap<&str, Vec<&Object>> {
+        self.objects_by_type_in_order(ObjectOrder::DrawOrder)
+    }
+    
+    /// Like `objects_by_type`, with each group in the given order
+    pub fn objects_by_type_in_order(&self, order: ObjectOrder) -> HashMap<&str, Vec<&Object>> {
+        let mut groups: HashMap<&str, Vec<&Object>> = HashMap::new();
+        for object in self.objects_in_order(order) {
+            groups.entry(&object.kind[..]).or_default().push(object);
+        }
+        groups
+    }
+    
+    /// `objects_by_type` with the types as owned strings, e.g. for keeping
+    /// the groups alongside types which don't borrow from the layer. Empty
+    /// when the layer has no objects.
+    pub fn cluster_by_type(&self) -> HashMap<String, Vec<&Object>> {
+        self.objects_by_type().into_iter().map(|(kind, objects)| (kind.to_string(), objects)).collect()
+    }
+    
+    /// The distinct object types in this layer
+    pub fn types(&self) -> HashSet<&str> {
+        self.objects.iter().map(|object| &object.kind[..]).collect()
+    }
+    
+    /// Indices into `tilesets` of the tilesets used by tile objects in this
+    /// layer, in ascending order. Gids which don't belong to any tileset are
+    /// skipped.
+    pub fn referenced_tilesets<S: Borrow<Tileset>>(&self, tilesets: &[S]) -> Vec<usize> {
+        referenced_tilesets(self.objects.iter().filter_map(|object| object.gid), tilesets)
+    }
+    
+    /// The object closest to the point `(x, y)` in pixels, along with its
+    /// distance, such as for finding what's under the cursor in an editor.
+    /// Objects with no size are measured from their center, and other
+    /// objects from the edge of their `bounding_rect`, so a point inside one
+    /// is at distance 0. Ties go to the object first in the list.
+    pub fn find_nearest_object(&self, x: f32, y: f32) -> Option<(f32, &Object)> {
+        let mut nearest: Option<(f32, &Object)> = None;
+        for object in &self.objects {
+            let distance = object.distance_to(x, y);
+            if nearest.is_none_or(|(best, _)| distance < best) {
+                nearest = Some((distance, object));
+            }
+        }
+        nearest
+    }
+    
+    /// Applies `f` to every object in this layer, in order. Handy for batch
+    /// edits like scaling every position after changing the tile size.
+    pub fn transform_all<F: FnMut(&mut Object)>(&mut self, mut f: F) {
+        for object in &mut self.objects {
+            f(object);
+        }
+    }
+}
+
+/// A layer showing a single image, such as a background
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImageLayer {
+    pub name: String,
+    /// Unique ID of the layer, written by Tiled 1.2 and newer
+    pub id: Option<u32>,
+    pub opacity: f32,
+    #[serde(default)]
+    pub properties: Properties,
+    pub visible: bool,
+    pub x: f32,
+    pub y: f32,
+    pub offsetx: Option<f32>,
+    pub offsety: Option<f32>,
+    pub parallaxx: Option<f32>,
+    pub parallaxy: Option<f32>,
+    /// Whether the layer is locked against editing in Tiled
+    pub locked: Option<bool>,
+    /// The index in the level's `layers` this layer was loaded from, when
+    /// `LoadOptions::record_source_pointers` is set. See
+    /// `Layer::source_pointer`.
+    #[serde(skip_deserializing)]
+    pub source_index: Option<usize>,
+    
+    /// Path to the image. When the level is loaded from a file this is
+    /// relative to the current directory, like tileset images; otherwise it
+    /// is as written in the JSON. Empty if the layer has no image.
+    pub image: PathBuf,
+    /// Whether the image is repeated to fill the view horizontally, written
+    /// by Tiled 1.8 and newer
+    #[serde(default)]
+    pub repeatx: bool,
+    /// Whether the image is repeated to fill the view vertically
+    #[serde(default)]
+    pub repeaty: bool,
+}
+
+impl ImageLayer {
+    /// Wraps this layer in a `Layer`, e.g. for building a level's layer list
+    pub fn to_layer(self) -> Layer {
+        Layer::Images(self)
+    }
+    
+    /// The parallax scrolling factor of this layer, defaulting to 1.0
+    /// along each axis
+    pub fn parallax(&self) -> (f32, f32) {
+        (self.parallaxx.unwrap_or(1.0), self.parallaxy.unwrap_or(1.0))
+    }
+    
+    /// Where to draw this layer's image, `image_width` by `image_height`
+    /// pixels, so that it covers as much of `view` as it should. The image is
+    /// placed at the layer's offset, shifted for parallax scrolling with the
+    /// view centered like `Level::parallax_offset`, and repeated along the
+    /// axes with `repeatx` or `repeaty` set. Only placements which overlap
+    /// the view are returned, row by row from the top-left. An empty view or
+    /// image gives no placements.
+    pub fn draw_rects(&self, level: &Level, view: BoundingRect, image_width: u32, image_height: u32) -> Vec<BoundingRect> {
+        if image_width == 0 || image_height == 0 || !(view.width > 0.0 && view.height > 0.0) {
+            return Vec::new();
+        }
+        
+        let center = (view.x + view.width / 2.0, view.y + view.height / 2.0);
+        let (shift_x, shift_y) = level.parallax_shift(self.parallax(), center.0, center.1);
+        let x = self.offsetx.unwrap_or(0.0) + shift_x;
+        let y = self.offsety.unwrap_or(0.0) + shift_y;
+        
+        let (width, height) = (image_width as f32, image_height as f32);
+        let columns = placements(x, width, view.x, view.x + view.width, self.repeatx);
+        let rows = placements(y, height, view.y, view.y + view.height, self.repeaty);
+        
+        let mut rects = Vec::with_capacity(columns.len() * rows.len());
+        for &y in &rows {
+            for &x in &columns {
+                rects.push(BoundingRect { x, y, width, height });
+            }
+        }
+        rects
+    }
+}
+
+/// The positions along one axis to draw an image of length `size` at so that
+/// it overlaps `start..end`: just `position` if it overlaps and isn't
+/// repeated, or every repeat which overlaps. Positions are counted rather
+/// than stepped to so that a tiny image far from the origin can't get stuck
+/// adding a size too small to change the position.
+fn placements(position: f32, size: f32, start: f32, end: f32, repeat: bool) -> Vec<f32> {
+    if !repeat {
+        return if position < end && position + size > start { vec![position] } else { Vec::new() };
+    }
+    
+    let first = ((start - position) / size).floor();
+    let count = ((end - position) / size).ceil() - first;
+    if !first.is_finite() || !count.is_finite() || count <= 0.0 {
+        return Vec::new();
+    }
+    (0..count as u32).map(|i| position + (first + i as f32) * size).collect()
+}
+
+/// An order to visit the objects of an object layer in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ObjectOrder {
+    /// The order the layer is drawn in, given by its `draworder`
+    DrawOrder,
+    /// The order of the layer's list of objects, which Tiled draws in when
+    /// `draworder` is `"index"`. Things like triggers which fire in sequence
+    /// may rely on it.
+    Index,
+    /// By ascending y position, with objects at the same y in list order.
+    /// Tiled draws in this order when `draworder` is `"topdown"`.
+    TopDown,
+}
+
+/// Changes the flip flags of a tile with `f`, leaving empty cells alone.
+/// Mirroring a tile on screen always toggles the matching flag, even when the
+/// tile is also flipped diagonally, since Tiled applies that flip first.
+fn toggle_flip<F: FnOnce(&mut TileFlip)>(gid: GlobalTile, f: F) -> GlobalTile {
+    if gid.is_empty() {
+        return gid;
+    }
+    let mut flip = TileFlip::from_gid(gid);
+    f(&mut flip);
+    flip.apply_to(gid)
+}
+
+fn referenced_tilesets<I, S>(gids: I, tilesets: &[S]) -> Vec<usize>
+    where I: Iterator<Item = GlobalTile>, S: Borrow<Tileset>
+{
+    let mut used = vec![false; tilesets.len()];
+    for gid in gids.filter(|gid| !gid.is_empty()) {
+        if let Some((i, _)) = gid.find_local(tilesets) {
+            used[i] = true;
+        }
+    }
+    used.iter().enumerate().filter(|&(_, &used)| used).map(|(i, _)| i).collect()
+}
+
+/// Deserializes the objects of an object layer one at a time, so that errors
+/// include the JSON pointer of the object and field which failed
+fn deserialize_objects<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Object>, D::Error> {
+    use serde::de::Error;
+    
+    let values: Vec<JsonValue> = Deserialize::deserialize(d)?;
+    
+    let mut objects = Vec::with_capacity(values.len());
+    for (i, value) in values.iter().enumerate() {
+        match from_value_at_field(value) {
+            Ok(object) => objects.push(object),
+            Err(e) => return Err(D::Error::custom(at_pointer(&format!("/objects/{}", i), &e))),
+        }
+    }
+    Ok(objects)
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Object {
     pub id: u32,
     pub name: String,
     
-    #[serde(rename = "type")]
-    pub _type: String,
+    /// The object's type, or class as newer versions of Tiled call it. This
+    /// is read from either `type` or `class` in the JSON, since Tiled 1.9
+    /// renamed it to `class`.
+    #[serde(rename = "type", alias = "class")]
+    pub kind: String,
+    /// The tile shown by a tile object. Gid 0 never refers to a tile, so
+    /// objects written with `"gid": 0` are loaded as if they had no gid.
+    #[serde(default, deserialize_with = "deserialize_object_gid")]
     pub gid: Option<GlobalTile>,
     pub ellipse: Option<bool>,
     pub polygon: Option<Vec<PolyPoint>>,
     
-    pub properties: HashMap<String, String>,
+    pub properties: Properties,
     pub rotation: f32,
     pub visible: bool,
     
@@ -99,10 +1033,442 @@ pub struct Object {
     
     pub x: f32,
     pub y: f32,
+    
+    /// The JSON this object was loaded from, including any keys this crate
+    /// doesn't know about. Only kept when `LoadOptions::keep_raw_objects` is
+    /// set.
+    #[serde(skip_deserializing)]
+    pub raw: Option<Box<JsonValue>>,
+    /// The indices of the layer and the object within it this object was
+    /// loaded from, when `LoadOptions::record_source_pointers` is set. See
+    /// `source_pointer`.
+    #[serde(skip_deserializing)]
+    pub source_index: Option<(usize, usize)>,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+fn deserialize_object_gid<'de, D: Deserializer<'de>>(d: D) -> Result<Option<GlobalTile>, D::Error> {
+    let gid: Option<GlobalTile> = Deserialize::deserialize(d)?;
+    Ok(gid.and_then(|gid| if gid.is_empty() { None } else { Some(gid) }))
+}
+
+impl Object {
+    #[deprecated(note = "the field is now called `kind`")]
+    pub fn _type(&self) -> &str {
+        &self.kind
+    }
+    
+    /// Where in the level's JSON this object was loaded from, e.g.
+    /// `/layers/3/objects/7`, if the level was loaded with
+    /// `LoadOptions::record_source_pointers`. Like `Layer::source_pointer`
+    /// this stays the same however the level is changed after loading.
+    pub fn source_pointer(&self) -> Option<String> {
+        self.source_index.map(|(layer, object)| format!("/layers/{}/objects/{}", layer, object))
+    }
+    
+    /// Sets a custom property, for building objects in a chain of calls
+    pub fn with_property<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Object {
+        self.properties.set(key, value);
+        self
+    }
+    
+    /// Removes a custom property, for building objects in a chain of calls
+    pub fn without_property(mut self, key: &str) -> Object {
+        self.properties.unset(key);
+        self
+    }
+    
+    /// The smallest axis-aligned rectangle containing the object, in pixels,
+    /// taking its rotation into account. Tile objects extend up from their
+    /// position and polygons cover their points.
+    pub fn bounding_rect(&self) -> BoundingRect {
+        let corners = match self.polygon {
+            Some(ref polygon) if !polygon.is_empty() => polygon.clone(),
+            _ => {
+                // Tile objects have their origin at the bottom-left
+                let top = if self.gid.is_some() { -self.height } else { 0.0 };
+                let bottom = top + self.height;
+                vec![
+                    PolyPoint { x: 0.0, y: top },
+                    PolyPoint { x: self.width, y: top },
+                    PolyPoint { x: self.width, y: bottom },
+                    PolyPoint { x: 0.0, y: bottom },
+                ]
+            }
+        };
+        
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+        let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for corner in corners {
+            let x = self.x + corner.x * cos - corner.y * sin;
+            let y = self.y + corner.x * sin + corner.y * cos;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        BoundingRect { x: min_x, y: min_y, width: max_x - min_x, height: max_y - min_y }
+    }
+    
+    /// The center of the object's `bounding_rect`. For point objects this is
+    /// just their position.
+    pub fn center(&self) -> (f32, f32) {
+        let rect = self.bounding_rect();
+        (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0)
+    }
+    
+    /// The distance in pixels from `(x, y)` to the object, as measured by
+    /// `ObjectLayer::find_nearest_object`
+    fn distance_to(&self, x: f32, y: f32) -> f32 {
+        let rect = self.bounding_rect();
+        if rect.width == 0.0 && rect.height == 0.0 {
+            let (center_x, center_y) = self.center();
+            return (x - center_x).hypot(y - center_y);
+        }
+        
+        let dx = (rect.x - x).max(x - (rect.x + rect.width)).max(0.0);
+        let dy = (rect.y - y).max(y - (rect.y + rect.height)).max(0.0);
+        dx.hypot(dy)
+    }
+    
+    /// Mirrors the object across the vertical line at `x = map_width / 2`.
+    /// The mirrored object's origin is where its right edge was, so rotated
+    /// objects and tile objects (whose origin is at the bottom-left) end up
+    /// in the right place, and the tile of a tile object is flipped too.
+    pub(crate) fn mirror_horizontal(&mut self, map_width: f32) {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (right_x, right_y) = (self.x + self.width * cos, self.y + self.width * sin);
+        self.x = map_width - right_x;
+        self.y = right_y;
+        self.rotation = -self.rotation;
+        if let Some(ref mut polygon) = self.polygon {
+            for point in polygon {
+                point.x = -point.x;
+            }
+        }
+        self.gid = self.gid.map(|gid| toggle_flip(gid, |flip| flip.horizontal = !flip.horizontal));
+    }
+    
+    /// Mirrors the object across the horizontal line at `y = map_height / 2`,
+    /// like `mirror_horizontal`
+    pub(crate) fn mirror_vertical(&mut self, map_height: f32) {
+        // The origin is the top-left corner, or the bottom-left for tile
+        // objects, and it moves to the opposite corner along the left edge
+        let height = if self.gid.is_some() { -self.height } else { self.height };
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (corner_x, corner_y) = (self.x - height * sin, self.y + height * cos);
+        self.x = corner_x;
+        self.y = map_height - corner_y;
+        self.rotation = -self.rotation;
+        if let Some(ref mut polygon) = self.polygon {
+            for point in polygon {
+                point.y = -point.y;
+            }
+        }
+        self.gid = self.gid.map(|gid| toggle_flip(gid, |flip| flip.vertical = !flip.vertical));
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
 pub struct PolyPoint {
     pub x: f32,
     pub y: f32,
 }
+
+impl PolyPoint {
+    /// This point rotated by `angle_radians` around `origin`. With y pointing
+    /// down, as in Tiled, positive angles turn clockwise on screen like
+    /// `Object::rotation` does.
+    pub fn rotate(self, angle_radians: f32, origin: PolyPoint) -> PolyPoint {
+        let (sin, cos) = angle_radians.sin_cos();
+        let (x, y) = (self.x - origin.x, self.y - origin.y);
+        PolyPoint {
+            x: origin.x + x * cos - y * sin,
+            y: origin.y + x * sin + y * cos,
+        }
+    }
+}
+
+/// Rotates every point of a polygon or polyline by `angle` radians around
+/// `origin`, as `PolyPoint::rotate` does
+pub fn polygon_rotate(points: &mut [PolyPoint], angle: f32, origin: PolyPoint) {
+    for point in points.iter_mut() {
+        *point = point.rotate(angle, origin);
+    }
+}
+
+/// An axis-aligned rectangle in pixels, with `(x, y)` its top-left corner
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[test]
+fn border_tiles_order() {
+    use serde_json;
+    
+    let layer: TileLayer = serde_json::from_str(r#"{
+        "name": "border", "opacity": 1, "visible": true,
+        "width": 3, "height": 3, "x": 0, "y": 0,
+        "data": [1, 2, 3, 4, 5, 6, 7, 8, 9]
+    }"#).unwrap();
+    
+    let tiles: Vec<u32> = layer.border_tiles().map(|(_, tile)| tile.0).collect();
+    assert_eq!(tiles, vec![1, 2, 3, 6, 9, 8, 7, 4]);
+}
+
+#[test]
+fn expand_and_shrink() {
+    let mut layer = TileLayer::generate_from("grow", 2, 2, |x, y| GlobalTile(1 + x + 2 * y));
+    let fill = GlobalTile(9);
+    
+    layer.expand(3, 2, fill).unwrap();
+    assert_eq!(layer.data, [1, 2, 9, 3, 4, 9].iter().map(|&id| GlobalTile(id)).collect::<Vec<_>>());
+    layer.expand(3, 3, fill).unwrap();
+    assert_eq!((layer.width, layer.height, layer.data.len()), (3, 3, 9));
+    assert_eq!(layer.row(2), Some(&[fill, fill, fill][..]));
+    
+    let error = layer.expand(2, 4, fill).unwrap_err();
+    assert_eq!(error.to_string(), "Can't resize layer from 3x3 to 2x4");
+    assert!(layer.shrink(4, 1).is_err());
+    assert_eq!((layer.width, layer.height), (3, 3));
+    
+    layer.shrink(2, 1).unwrap();
+    assert_eq!(layer.data, vec![GlobalTile(1), GlobalTile(2)]);
+    layer.shrink(0, 0).unwrap();
+    assert!(layer.data.is_empty());
+}
+
+#[test]
+fn quarter_turn_transform() {
+    let mut layer = TileLayer::generate_from("turn", 2, 2, |x, y| GlobalTile(1 + x + 2 * y));
+    let turn = Transform2D { matrix: [[0.0, -1.0], [1.0, 0.0]], translation: [2.0, 0.0] };
+    layer.apply_transform(&turn).unwrap();
+    
+    let turned = TileFlip { horizontal: true, vertical: false, diagonal: true };
+    let expected: Vec<GlobalTile> = [3, 1, 4, 2].iter().map(|&id| turned.apply_to(GlobalTile(id))).collect();
+    assert_eq!(layer.data, expected);
+    
+    let flat = Transform2D { matrix: [[1.0, 0.0], [0.0, 0.0]], ..Transform2D::IDENTITY };
+    assert!(layer.apply_transform(&flat).is_err());
+    
+    // Mirroring back and forth restores the layer, flags included
+    let before = layer.data.clone();
+    layer.flip_horizontal();
+    assert_eq!(layer.tile_at(0, 0), Some(TileFlip { diagonal: true, ..TileFlip::default() }.apply_to(GlobalTile(1))));
+    layer.flip_horizontal();
+    assert_eq!(layer.data, before);
+}
+
+#[test]
+fn world_to_tile_with_offset() {
+    use std::convert::TryFrom;
+    
+    fn level(orientation: &str) -> Level {
+        Level::try_from(&format!(r#"{{
+            "height": 4, "width": 4, "tileheight": 32, "tilewidth": 64,
+            "orientation": "{}", "renderorder": "right-down", "properties": {{}},
+            "layers": [{{
+                "type": "tilelayer", "name": "offset", "opacity": 1, "visible": true,
+                "width": 4, "height": 4, "x": 0, "y": 0, "offsetx": 10, "offsety": 20,
+                "data": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+            }}],
+            "tilesets": []
+        }}"#, orientation)[..]).unwrap()
+    }
+    
+    let ortho = level("orthogonal");
+    let layer = match ortho.layers[0] { Layer::Tiles(ref tiles) => tiles, _ => unreachable!() };
+    assert_eq!(layer.world_to_tile(&ortho, 10.0 + 64.0 * 2.0 + 1.0, 20.0 + 32.0 + 1.0), Some(TileCoord { x: 2, y: 1 }));
+    assert_eq!(layer.world_to_tile(&ortho, 9.0, 19.0), None);
+    assert_eq!(layer.tile_to_world(&ortho, 2, 1), (138.0, 52.0));
+    
+    // The top corner of tile (1, 0) is at (160, 16) before the offset is applied
+    let iso = level("isometric");
+    let layer = match iso.layers[0] { Layer::Tiles(ref tiles) => tiles, _ => unreachable!() };
+    assert_eq!(layer.tile_to_world(&iso, 1, 0), (170.0, 36.0));
+    assert_eq!(layer.world_to_tile(&iso, 170.0, 52.0), Some(TileCoord { x: 1, y: 0 }));
+    assert_eq!(layer.world_to_tile(&iso, 170.0, 30.0), None);
+}
+
+#[test]
+fn object_gid_zero_is_absent() {
+    use serde_json;
+    
+    let layer: ObjectLayer = serde_json::from_str(r#"{
+        "name": "objects", "opacity": 1, "visible": true, "draworder": "topdown",
+        "width": 0, "height": 0, "x": 0, "y": 0,
+        "objects": [
+            { "id": 1, "name": "", "type": "", "properties": {}, "rotation": 0, "visible": true,
+              "width": 8, "height": 8, "x": 0, "y": 0 },
+            { "id": 2, "name": "", "type": "", "properties": {}, "rotation": 0, "visible": true,
+              "width": 8, "height": 8, "x": 0, "y": 0, "gid": 0 },
+            { "id": 3, "name": "", "type": "", "properties": {}, "rotation": 0, "visible": true,
+              "width": 8, "height": 8, "x": 0, "y": 0, "gid": 5 }
+        ]
+    }"#).unwrap();
+    
+    let gids: Vec<Option<GlobalTile>> = layer.objects.iter().map(|object| object.gid).collect();
+    assert_eq!(gids, vec![None, None, Some(GlobalTile(5))]);
+}
+
+#[test]
+fn object_class_is_its_kind() {
+    use serde_json;
+    
+    let object: Object = serde_json::from_str(r#"{
+        "id": 1, "name": "", "class": "door", "properties": {}, "rotation": 0, "visible": true,
+        "width": 8, "height": 8, "x": 0, "y": 0
+    }"#).unwrap();
+    assert_eq!(object.kind, "door");
+}
+
+#[test]
+fn objects_follow_draw_order() {
+    use serde_json;
+    
+    let layer = |draworder: &str| -> ObjectLayer {
+        serde_json::from_str(&r#"{
+            "name": "objects", "opacity": 1, "visible": true, "draworder": "ORDER",
+            "width": 0, "height": 0, "x": 0, "y": 0,
+            "objects": [
+                { "id": 1, "name": "", "type": "step", "properties": {}, "rotation": 0, "visible": true,
+                  "width": 8, "height": 8, "x": 0, "y": 32 },
+                { "id": 2, "name": "", "type": "step", "properties": {}, "rotation": 0, "visible": true,
+                  "width": 8, "height": 8, "x": 0, "y": 16 }
+            ]
+        }"#.replace("ORDER", draworder)).unwrap()
+    };
+    let ids = |objects: &[&Object]| objects.iter().map(|object| object.id).collect::<Vec<u32>>();
+    
+    let index = layer("index");
+    assert_eq!(index.draw_order(), ObjectOrder::Index);
+    assert_eq!(ids(&index.objects_by_type()["step"]), vec![1, 2]);
+    assert_eq!(ids(&index.objects_by_type_in_order(ObjectOrder::TopDown)["step"]), vec![2, 1]);
+    
+    let topdown = layer("topdown");
+    assert_eq!(topdown.draw_order(), ObjectOrder::TopDown);
+    assert_eq!(ids(&topdown.objects_by_type()["step"]), vec![2, 1]);
+    assert_eq!(ids(&topdown.objects_in_order(ObjectOrder::Index)), vec![1, 2]);
+}
+
+#[test]
+fn csv_tile_data() {
+    assert_eq!(parse_csv_data("1, 2,0,\n3\n").unwrap(), vec![GlobalTile(1), GlobalTile(2), GlobalTile(0), GlobalTile(3)]);
+    assert_eq!(parse_csv_data("").unwrap(), vec![]);
+    assert_eq!(parse_csv_data("1,2,x,4").unwrap_err(), "/data/2: `x` is not a tile id");
+}
+
+#[test]
+fn nearest_object() {
+    use serde_json;
+    
+    let layer: ObjectLayer = serde_json::from_str(r#"{
+        "name": "things", "opacity": 1, "visible": true, "x": 0, "y": 0,
+        "width": 0, "height": 0, "draworder": "topdown",
+        "objects": [
+            { "id": 1, "name": "spawn", "type": "", "properties": {}, "visible": true,
+              "rotation": 0, "x": 10, "y": 10, "width": 0, "height": 0 },
+            { "id": 2, "name": "crate", "type": "", "properties": {}, "visible": true, "gid": 5,
+              "rotation": 0, "x": 100, "y": 40, "width": 20, "height": 20 }
+        ]
+    }"#).unwrap();
+    
+    assert_eq!(layer.objects[1].bounding_rect(), BoundingRect { x: 100.0, y: 20.0, width: 20.0, height: 20.0 });
+    assert_eq!(layer.objects[1].center(), (110.0, 30.0));
+    
+    let (distance, object) = layer.find_nearest_object(13.0, 14.0).unwrap();
+    assert_eq!((distance, object.id), (5.0, 1));
+    let (distance, object) = layer.find_nearest_object(105.0, 25.0).unwrap();
+    assert_eq!((distance, object.id), (0.0, 2));
+    let (distance, object) = layer.find_nearest_object(90.0, 30.0).unwrap();
+    assert_eq!((distance, object.id), (10.0, 2));
+    
+    let empty = ObjectLayer { objects: Vec::new(), ..layer };
+    assert!(empty.find_nearest_object(0.0, 0.0).is_none());
+}
+
+#[test]
+fn image_layer_placements() {
+    use serde_json;
+    
+    let layer: ImageLayer = serde_json::from_str(r#"{
+        "type": "imagelayer", "name": "sky", "image": "sky.png", "opacity": 1, "visible": true,
+        "x": 0, "y": 0, "offsetx": 10, "offsety": 20, "repeatx": true
+    }"#).unwrap();
+    assert!(!layer.repeaty);
+    
+    let level = Level::empty();
+    let view = BoundingRect { x: 0.0, y: 0.0, width: 250.0, height: 100.0 };
+    let xs: Vec<f32> = layer.draw_rects(&level, view, 100, 50).iter().map(|rect| rect.x).collect();
+    assert_eq!(xs, vec![-90.0, 10.0, 110.0, 210.0]);
+    assert!(layer.draw_rects(&level, view, 0, 50).is_empty());
+    
+    // Not repeated vertically, so nothing is drawn once the image is out of view
+    let below = BoundingRect { x: 0.0, y: 500.0, width: 250.0, height: 100.0 };
+    assert!(layer.draw_rects(&level, below, 100, 50).is_empty());
+}
+
+#[test]
+fn palette_swap() {
+    let flipped = TileFlip { horizontal: true, vertical: false, diagonal: false };
+    let layer = TileLayer {
+        width: 3,
+        height: 1,
+        data: vec![GlobalTile(1), flipped.apply_to(GlobalTile(1)), GlobalTile(2)],
+        ..TileLayer::EMPTY
+    };
+    let palette: HashMap<GlobalTile, GlobalTile> = vec![(GlobalTile(1), GlobalTile(7))].into_iter().collect();
+    
+    let swapped = layer.apply_palette(&palette);
+    assert_eq!(swapped.data, vec![GlobalTile(7), flipped.apply_to(GlobalTile(7)), GlobalTile(2)]);
+}
+
+#[test]
+fn scatter_decorations() {
+    let mut tileset = Tileset::placeholder("decor");
+    tileset.firstgid = GlobalTile(11);
+    tileset.tilecount = 3;
+    tileset.tiles.probabilities.insert(LocalTile(1), 0.0);
+    
+    let ground = TileLayer {
+        width: 2,
+        height: 2,
+        data: vec![GlobalTile(1), GlobalTile::EMPTY, GlobalTile(1), GlobalTile(1)],
+        ..TileLayer::EMPTY
+    };
+    let mask = ground.mask_from_predicate(|tile| !tile.is_empty());
+    let mut decor = TileLayer { data: vec![GlobalTile::EMPTY; 4], ..ground.clone() };
+    
+    let mut rolls = vec![0.9, 0.5, 0.1].into_iter();
+    decor.scatter(&mask, &tileset, &[LocalTile(0), LocalTile(1), LocalTile(2)], || rolls.next().unwrap()).unwrap();
+    assert_eq!(decor.data, vec![GlobalTile(13), GlobalTile::EMPTY, GlobalTile(13), GlobalTile(11)]);
+    
+    let small = TileLayer { width: 1, height: 1, data: vec![GlobalTile(1)], ..TileLayer::EMPTY };
+    assert!(decor.scatter(&small, &tileset, &[LocalTile(0)], || 0.0).is_err());
+    
+    // Candidates past the end of the tileset, or whose gid would overflow,
+    // are rejected before anything is written
+    let error = decor.scatter(&mask, &tileset, &[LocalTile(0), LocalTile(3)], || 0.0).unwrap_err();
+    assert_eq!(error.to_string(), "No tile 3 in tileset `decor`");
+    tileset.firstgid = GlobalTile(u32::MAX - 1);
+    assert!(decor.scatter(&mask, &tileset, &[LocalTile(2)], || 0.0).is_err());
+    assert_eq!(decor.data, vec![GlobalTile(13), GlobalTile::EMPTY, GlobalTile(13), GlobalTile(11)]);
+}
+
+#[test]
+fn rotate_points() {
+    use std::f32::consts::PI;
+    
+    let close = |a: PolyPoint, b: PolyPoint| (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4;
+    let origin = PolyPoint { x: 1.0, y: 1.0 };
+    
+    // A quarter turn clockwise takes a point right of the origin to below it
+    let mut points = vec![PolyPoint { x: 3.0, y: 1.0 }, origin];
+    polygon_rotate(&mut points, PI / 2.0, origin);
+    assert!(close(points[0], PolyPoint { x: 1.0, y: 3.0 }));
+    assert!(close(points[1], origin));
+}
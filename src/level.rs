@@ -1,18 +1,35 @@
-use std::path::Path;
-use std::fs::File;
-use std::collections::HashMap;
-use layer::Layer;
-use tileset::Tileset;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::cmp::max;
+use std::convert::TryFrom;
+use dependencies::{self, Dependencies};
+use error::{TiledError, json_error_message, pointer_error};
+use format::FormatProfile;
+use instrument::Timer;
+use layer::{Layer, TileLayer, ObjectLayer, ImageLayer, Object, ObjectOrder};
+use options::{LoadOptions, GidIntegrity};
+use properties::{Properties, property_text};
+use schema;
+use tileset::{Tileset, TilesetOrigin};
+use {GlobalTile, LocalTile, TileCoord, TileFlip, FLIP_FLAGS, parse_json, read_file, resolve_relative};
 use serde_json;
 use serde_json::Value as JsonValue;
 use serde_json::Error as JsonError;
 
+/// The most tiles a layer can be padded out to when its data is short and
+/// `LoadOptions::lenient_layer_data` is set. Without a limit, a small file
+/// declaring a huge layer could make loading allocate without bound.
+pub const MAX_PADDED_LAYER_TILES: u64 = 1 << 24;
+
 #[derive(Clone, Debug)]
 pub struct Level {
     pub height: u32,
     pub width: u32,
     
-    pub properties: HashMap<String, String>,
+    pub properties: Properties,
     
     pub orientation: String,
     pub renderorder: String,
@@ -20,20 +37,272 @@ pub struct Level {
     pub tileheight: u32,
     pub tilewidth: u32,
     
+    /// Point in pixels at which layer parallax factors have no effect
+    pub parallaxoriginx: f32,
+    pub parallaxoriginy: f32,
+    
+    /// The id the next object created in this level should be given
+    pub nextobjectid: u32,
+    
     pub layers: Vec<Layer>,
-    pub tilesets: Vec<Tileset>,
+    /// The level's tilesets. They are shared between clones of the level and
+    /// only copied when one of the clones changes them, see `tileset_mut`.
+    /// Indexing and slicing work as before: `&level.tilesets[i]` derefs to a
+    /// `&Tileset`, and `&level.tilesets[..]` can be passed to functions like
+    /// `GlobalTile::find_local`.
+    pub tilesets: Vec<Arc<Tileset>>,
+    
+    /// Problems which were found and corrected while loading the level
+    pub warnings: Vec<String>,
+    /// The format version the level was saved in and the optional parts of
+    /// the format it uses, including ones which were left out when loading
+    pub format: FormatProfile,
 }
 
 impl Level {
+    /// A 0x0 orthogonal level with no layers or tilesets, e.g. to stand in for
+    /// a level which hasn't finished loading yet
+    pub fn empty() -> Level {
+        Level {
+            height: 0,
+            width: 0,
+            
+            properties: Properties::new(),
+            
+            orientation: "orthogonal".to_string(),
+            renderorder: "right-down".to_string(),
+            
+            tileheight: 0,
+            tilewidth: 0,
+            
+            parallaxoriginx: 0.0,
+            parallaxoriginy: 0.0,
+            
+            nextobjectid: 1,
+            
+            layers: Vec::new(),
+            tilesets: Vec::new(),
+            
+            warnings: Vec::new(),
+            format: FormatProfile::default(),
+        }
+    }
+    
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Level, JsonError> {
-        let mut file = try!(File::open(&path));
-        let level: IntermediateLevel = try!(serde_json::from_reader(&mut file));
+        Level::load_with_options(path, &LoadOptions::default())
+    }
+    
+    pub fn load_with_options<P: AsRef<Path>>(path: P, options: &LoadOptions) -> Result<Level, JsonError> {
+        Level::load_cached(path, options, &mut HashMap::new())
+    }
+    
+    /// Loads every level file directly inside the directory at `path`: those
+    /// ending in `.json` or `.tmj`, so the directory shouldn't contain tileset
+    /// files with those extensions. See `load_many`; only failing to read the
+    /// directory itself is an error.
+    pub fn load_dir<P: AsRef<Path>>(path: P, options: &LoadOptions) -> Result<BatchResult, JsonError> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(path).map_err(JsonError::io)? {
+            let path = entry.map_err(JsonError::io)?.path();
+            let is_level = matches!(path.extension().and_then(|ext| ext.to_str()), Some("json") | Some("tmj"));
+            if is_level && path.is_file() {
+                paths.push(path);
+            }
+        }
+        Ok(Level::load_many(paths, options))
+    }
+    
+    /// Loads each of the level files at `paths`, carrying on past any which
+    /// fail so that every broken file is reported at once. Both the loaded
+    /// levels and the failures are sorted by path. An external tileset used
+    /// by several levels is only read once.
+    pub fn load_many<I, P>(paths: I, options: &LoadOptions) -> BatchResult
+        where I: IntoIterator<Item = P>, P: AsRef<Path>
+    {
+        let mut paths: Vec<PathBuf> = paths.into_iter().map(|path| path.as_ref().to_path_buf()).collect();
+        paths.sort();
+        paths.dedup();
+        
+        let mut cache = HashMap::new();
+        let mut result = BatchResult { loaded: Vec::new(), failed: Vec::new() };
+        for path in paths {
+            match Level::load_cached(&path, options, &mut cache) {
+                Ok(level) => result.loaded.push((path, level)),
+                Err(e) => result.failed.push((path, e)),
+            }
+        }
+        result
+    }
+    
+    /// Loads a level, reusing external tilesets already in `cache` and adding
+    /// the ones which weren't
+    fn load_cached<P: AsRef<Path>>(path: P, options: &LoadOptions, cache: &mut HashMap<PathBuf, Tileset>) -> Result<Level, JsonError> {
+        let _span = load_span!("load_level", path = %path.as_ref().display());
+        let timer = Timer::start();
+        load_debug!("Opening level {}", path.as_ref().display());
+        let json = read_file(path.as_ref())?;
+        let (level, format) = Level::parse_intermediate(parse_json(&json)?, options)?;
+        load_trace!("Parsed {} in {:.2}ms", path.as_ref().display(), timer.elapsed_ms());
+        
+        let mut level = Level::from_intermediate(level, format, options, |data| Tileset::load_cached(data, &path.as_ref(), cache))
+            .map_err(|e| e.locate(&json).into_json())?;
+        for layer in &mut level.layers {
+            if let Layer::Images(ref mut layer) = *layer {
+                if !layer.image.as_os_str().is_empty() {
+                    layer.image = resolve_relative(path.as_ref(), &layer.image.to_string_lossy());
+                }
+            }
+        }
+        load_debug!(
+            "Loaded level {} in {:.2}ms: {} layers, {} tilesets, {} warnings",
+            path.as_ref().display(), timer.elapsed_ms(),
+            level.layers.len(), level.tilesets.len(), level.warnings.len()
+        );
+        Ok(level)
+    }
+    
+    /// Loads a level from JSON in memory, e.g. a map uploaded to a server.
+    /// The bytes don't need to be valid UTF-8 for this to fail cleanly. Like
+    /// `Level::try_from`, image paths are left as written and external
+    /// tilesets can't be used because there is no path to find them
+    /// relative to; with `LoadOptions::allow_partial` they are replaced with
+    /// placeholders.
+    pub fn load_from_slice(json: &[u8], options: &LoadOptions) -> Result<Level, TiledError> {
+        use serde::de::Error;
+        
+        let (level, format) = Level::parse_intermediate(parse_json(json)?, options)?;
         
-        let tilesets: Vec<Tileset> = try!(level.tilesets.into_iter().map(|data| {
-            Tileset::load(data, &path.as_ref())
-        }).collect());
+        let is_external = |data: &JsonValue| match *data {
+            JsonValue::Object(ref data) => data.contains_key("source"),
+            _ => false,
+        };
+        if level.tilesets.iter().any(&is_external) && !options.allow_partial {
+            return Err(TiledError::ExternalTilesetNotSupported);
+        }
         
-        Ok(Level {
+        // With `allow_partial`, external tilesets become placeholders rather
+        // than being looked for relative to the current directory
+        Level::from_intermediate(level, format, options, |data| {
+            if is_external(&data) {
+                return Err(JsonError::custom(TiledError::ExternalTilesetNotSupported.to_string()));
+            }
+            Tileset::load(data, &"")
+        }).map_err(|e| e.locate(json))
+    }
+    
+    /// Checks that `json` has every field needed to load it as a level, with
+    /// the right types, without loading it. Unlike loading, this reports
+    /// every problem at once, each one with the JSON pointer of the field,
+    /// e.g. `/layers/0/data is not an array`. External tilesets are not read.
+    pub fn validate_schema(json: &str) -> Result<(), Vec<String>> {
+        let value: JsonValue = match parse_json(json.as_bytes()) {
+            Ok(value) => value,
+            Err(e) => return Err(vec![e.to_string()]),
+        };
+        
+        let problems = schema::check_level(&value);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+    
+    /// Works out the format of a level's JSON, then parses it. Fails naming
+    /// the features used if the format uses any which can't be loaded, rather
+    /// than with whatever deserialization error they would cause, unless
+    /// `options.allow_partial` is set.
+    fn parse_intermediate(json: JsonValue, options: &LoadOptions) -> Result<(IntermediateLevel, FormatProfile), JsonError> {
+        let format = FormatProfile::detect(&json);
+        if !options.allow_partial {
+            format.check_loadable()?;
+        }
+        let level = serde_json::from_value(json)?;
+        Ok((level, format))
+    }
+    
+    /// Finishes loading a level once its JSON has been parsed, using
+    /// `load_tileset` to turn each tileset entry into a Tileset
+    fn from_intermediate<F>(level: IntermediateLevel, format: FormatProfile, options: &LoadOptions, mut load_tileset: F) -> Result<Level, TiledError>
+        where F: FnMut(JsonValue) -> Result<Tileset, JsonError>
+    {
+        // Layers and tilesets are converted one at a time so that errors can
+        // say which one failed as a JSON pointer
+        let mut warnings = Vec::new();
+        let mut layers = Vec::with_capacity(level.layers.len());
+        for (i, data) in level.layers.into_iter().enumerate() {
+            let _span = load_span!("layer", index = i, name = data.get("name").and_then(JsonValue::as_str).unwrap_or(""));
+            let timer = Timer::start();
+            let placeholder = TileLayer {
+                name: data.get("name").and_then(JsonValue::as_str).unwrap_or("").to_string(),
+                id: data.get("id").and_then(JsonValue::as_u64).and_then(|id| u32::try_from(id).ok()),
+                ..TileLayer::EMPTY
+            };
+            let raw_objects = match (options.keep_raw_objects, data.get("objects")) {
+                (true, Some(JsonValue::Array(objects))) => objects.clone(),
+                _ => Vec::new(),
+            };
+            
+            match serde_json::from_value(data) {
+                Ok(mut layer) => {
+                    if let Layer::Objects(ref mut layer) = layer {
+                        for (object, raw) in layer.objects.iter_mut().zip(raw_objects) {
+                            object.raw = Some(Box::new(raw));
+                        }
+                    }
+                    load_trace!("Decoded layer {} `{}` in {:.2}ms", i, layer.name(), timer.elapsed_ms());
+                    layers.push(layer);
+                }
+                Err(e) if options.allow_partial => {
+                    warnings.push(format!(
+                        "Layer `{}` (/layers/{}) was replaced with an empty tile layer: {}",
+                        placeholder.name, i, json_error_message(&e)
+                    ));
+                    layers.push(Layer::Tiles(placeholder));
+                }
+                Err(e) => return Err(pointer_error(&format!("/layers/{}", i), &e)),
+            }
+        }
+        
+        let mut tilesets = Vec::with_capacity(level.tilesets.len());
+        let mut placeholders = Vec::new();
+        for (i, data) in level.tilesets.into_iter().enumerate() {
+            let _span = load_span!("tileset", index = i);
+            let timer = Timer::start();
+            let name = data.get("name").or_else(|| data.get("source"))
+                .and_then(JsonValue::as_str).unwrap_or("").to_string();
+            let firstgid = data.get("firstgid").and_then(JsonValue::as_u64).and_then(|gid| u32::try_from(gid).ok());
+            match load_tileset(data) {
+                Ok(tileset) => {
+                    load_trace!("Loaded tileset {} `{}` in {:.2}ms", i, tileset.name, timer.elapsed_ms());
+                    tilesets.push(Arc::new(tileset));
+                }
+                Err(e) if options.allow_partial => {
+                    warnings.push(format!(
+                        "Tileset `{}` (/tilesets/{}) was replaced with a placeholder: {}",
+                        name, i, json_error_message(&e)
+                    ));
+                    let mut placeholder = Tileset::placeholder(&name);
+                    if let Some(firstgid) = firstgid {
+                        placeholder.firstgid = GlobalTile(firstgid);
+                    }
+                    placeholders.push(placeholder.firstgid);
+                    tilesets.push(Arc::new(placeholder));
+                }
+                Err(e) => return Err(pointer_error(&format!("/tilesets/{}", i), &e)),
+            }
+        }
+        if options.record_source_pointers {
+            for (i, layer) in layers.iter_mut().enumerate() {
+                layer.record_source(i);
+            }
+            for (i, set) in tilesets.iter_mut().enumerate() {
+                Arc::make_mut(set).source_index = Some(i);
+            }
+        }
+        let has_nextobjectid = level.nextobjectid.is_some();
+        
+        let mut level = Level {
             height: level.height,
             width: level.width,
             
@@ -45,10 +314,1563 @@ impl Level {
             tileheight: level.tileheight,
             tilewidth: level.tilewidth,
             
-            layers: level.layers,
-            tilesets: tilesets,
+            parallaxoriginx: level.parallaxoriginx.unwrap_or(0.0),
+            parallaxoriginy: level.parallaxoriginy.unwrap_or(0.0),
+            
+            nextobjectid: level.nextobjectid.unwrap_or(1),
+            
+            layers,
+            tilesets,
+            
+            warnings,
+            format,
+        };
+        
+        // Maps saved by old versions of Tiled don't have nextobjectid
+        if !has_nextobjectid {
+            let max_id = level.object_layers().flat_map(|layer| &layer.objects).map(|o| o.id).max();
+            level.nextobjectid = max_id.map_or(1, |id| id.saturating_add(1));
+        }
+        
+        for id in level.duplicate_object_ids() {
+            level.warnings.push(format!("More than one object has id {}", id));
+        }
+        
+        if options.coerce_legacy_properties {
+            level.coerce_legacy_properties();
+        }
+        level.check_layer_sizes(options)?;
+        level.check_firstgids(options)?;
+        level.check_gids(options, &placeholders)?;
+        Ok(level)
+    }
+    
+    /// Gives untyped properties the type their value looks like, recording a
+    /// warning for each one
+    fn coerce_legacy_properties(&mut self) {
+        let mut warnings = Vec::new();
+        self.visit_properties_mut(|owner, properties| {
+            for (key, kind) in properties.coerce_untyped() {
+                warnings.push(format!("Property `{}` of {} was read as {}", key, owner, kind));
+            }
+        });
+        self.warnings.extend(warnings);
+    }
+    
+    /// Ensures the data of every tile layer matches its declared dimensions,
+    /// either fixing it or failing depending on `options.lenient_layer_data`
+    fn check_layer_sizes(&mut self, options: &LoadOptions) -> Result<(), JsonError> {
+        use serde::de::Error;
+        
+        for layer in &mut self.layers {
+            let tiles = match *layer {
+                Layer::Tiles(ref mut tiles) => tiles,
+                _ => continue,
+            };
+            
+            let expected = tiles.width as u64 * tiles.height as u64;
+            if tiles.data.len() as u64 == expected {
+                continue;
+            }
+            
+            let message = format!(
+                "Layer `{}` is {}x{} ({} tiles) but has {} tiles of data",
+                tiles.name, tiles.width, tiles.height, expected, tiles.data.len()
+            );
+            if !options.lenient_layer_data || expected > MAX_PADDED_LAYER_TILES {
+                return Err(JsonError::custom(message));
+            }
+            
+            self.warnings.push(message);
+            tiles.data.resize(expected as usize, GlobalTile::EMPTY);
+        }
+        
+        Ok(())
+    }
+    
+    /// Checks that every tileset's firstgid is in range, either recording a
+    /// warning or failing depending on `options.strict_firstgid`
+    fn check_firstgids(&mut self, options: &LoadOptions) -> Result<(), JsonError> {
+        use serde::de::Error;
+        
+        for set in &self.tilesets {
+            if let Some(message) = set.firstgid_problem() {
+                if options.strict_firstgid {
+                    return Err(JsonError::custom(message));
+                }
+                self.warnings.push(message);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Finds tile layer gids which don't refer to a tile in any tileset, and
+    /// either clears them or fails depending on `options.gid_integrity`.
+    /// Nothing is known about the tiles of a placeholder tileset, so gids
+    /// from its firstgid up to the next tileset's are left alone.
+    fn check_gids(&mut self, options: &LoadOptions, placeholders: &[GlobalTile]) -> Result<(), JsonError> {
+        use serde::de::Error;
+        
+        let tilesets = &self.tilesets;
+        let warnings = &mut self.warnings;
+        for layer in &mut self.layers {
+            let layer = match *layer {
+                Layer::Tiles(ref mut layer) => layer,
+                _ => continue,
+            };
+            
+            for (i, tile) in layer.data.iter_mut().enumerate() {
+                let in_placeholder = range_owner(tile.strip_flags().0, tilesets)
+                    .is_some_and(|set| placeholders.contains(&set.firstgid));
+                if in_placeholder {
+                    continue;
+                }
+                let problem = match gid_problem(*tile, tilesets) {
+                    Some(problem) => problem,
+                    None => continue,
+                };
+                
+                let message = format!("Layer `{}` tile {}: {}", layer.name, i, problem);
+                match options.gid_integrity {
+                    GidIntegrity::Clear => {
+                        warnings.push(message);
+                        *tile = GlobalTile(0);
+                    }
+                    GidIntegrity::Error => return Err(JsonError::custom(message)),
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Reloads every external tileset from its file, returning whether any of
+    /// them changed. If a tileset now has fewer tiles and the level uses
+    /// tiles which no longer exist, a warning is recorded for each one; the
+    /// gids themselves are left alone.
+    pub fn reload_tilesets(&mut self) -> Result<bool, JsonError> {
+        let mut changed = false;
+        for set in &mut self.tilesets {
+            // Tilesets shared with clones of the level stay shared unless
+            // their file changed
+            if let Some(tileset) = set.reloaded()? {
+                *set = Arc::new(tileset);
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok(false);
+        }
+        
+        for layer in &self.layers {
+            match *layer {
+                Layer::Tiles(ref tiles) => {
+                    for (i, &tile) in tiles.data.iter().enumerate() {
+                        if let Some(problem) = gid_problem(tile, &self.tilesets) {
+                            self.warnings.push(format!("Layer `{}` tile {}: {}", tiles.name, i, problem));
+                        }
+                    }
+                }
+                Layer::Objects(ref objects) => {
+                    for object in &objects.objects {
+                        let problem = object.gid.and_then(|gid| gid_problem(gid, &self.tilesets));
+                        if let Some(problem) = problem {
+                            self.warnings.push(format!(
+                                "Layer `{}` object {}: {}", objects.name, object.id, problem
+                            ));
+                        }
+                    }
+                }
+                Layer::Images(_) => {}
+            }
+        }
+        Ok(true)
+    }
+    
+    /// Finds every file the level at `path` depends on without loading it:
+    /// external tilesets and templates along with the files they reference,
+    /// tileset and image layer images, and file-typed properties. This only
+    /// parses the JSON, so it is much faster than loading when scanning many
+    /// levels. Files which are missing or can't be read are reported as
+    /// warnings; only failing to read the level is an error.
+    pub fn dependencies<P: AsRef<Path>>(path: P) -> Result<Dependencies, JsonError> {
+        dependencies::scan_level(path.as_ref())
+    }
+    
+    /// Every file this level depends on besides the level file itself: the
+    /// external tileset files, all tileset images and the images of image
+    /// layers, without duplicates.
+    pub fn external_dependencies(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = Vec::new();
+        for set in &self.tilesets {
+            if let TilesetOrigin::External { ref source } = set.origin {
+                if !files.contains(source) {
+                    files.push(source.clone());
+                }
+            }
+            if !files.contains(&set.image) {
+                files.push(set.image.clone());
+            }
+        }
+        for layer in self.image_layers() {
+            if !layer.image.as_os_str().is_empty() && !files.contains(&layer.image) {
+                files.push(layer.image.clone());
+            }
+        }
+        files
+    }
+    
+    /// Iterates all of the tile layers in this level
+    pub fn tile_layers(&self) -> impl Iterator<Item = &TileLayer> {
+        self.layers.iter().filter_map(|layer| match *layer {
+            Layer::Tiles(ref tiles) => Some(tiles),
+            _ => None,
+        })
+    }
+    
+    /// Iterates all of the object layers in this level
+    pub fn object_layers(&self) -> impl Iterator<Item = &ObjectLayer> {
+        self.layers.iter().filter_map(|layer| match *layer {
+            Layer::Objects(ref objects) => Some(objects),
+            _ => None,
+        })
+    }
+    
+    /// Iterates all of the image layers in this level
+    pub fn image_layers(&self) -> impl Iterator<Item = &ImageLayer> {
+        self.layers.iter().filter_map(|layer| match *layer {
+            Layer::Images(ref image) => Some(image),
+            _ => None,
+        })
+    }
+    
+    /// The raw gids of the tile layer called `layer_name` as text, one row per
+    /// line with the gids in each row separated by `separator`. Handy for
+    /// looking at a layout while debugging, or for snapshot tests. Fails with
+    /// `LayerNotFound` if no top-level tile layer has that name.
+    pub fn to_tilemap_string(&self, layer_name: &str, separator: char) -> Result<String, TiledError> {
+        let layer = match self.tile_layers().find(|layer| layer.name == layer_name) {
+            Some(layer) => layer,
+            None => return Err(TiledError::LayerNotFound(layer_name.to_string())),
+        };
+        
+        let separator = separator.to_string();
+        let rows: Vec<String> = layer.iter_rows().map(|row| {
+            row.iter().map(|tile| tile.0.to_string()).collect::<Vec<_>>().join(&separator)
+        }).collect();
+        Ok(rows.join("\n"))
+    }
+    
+    /// Whether a top-level tile layer has the given name, e.g. for checking
+    /// that a map has the layers a game expects
+    pub fn has_tile_layer_named(&self, name: &str) -> bool {
+        self.tile_layers().any(|layer| layer.name == name)
+    }
+    
+    /// Whether a top-level object layer has the given name
+    pub fn has_object_layer_named(&self, name: &str) -> bool {
+        self.object_layers().any(|layer| layer.name == name)
+    }
+    
+    /// Whether a tileset has the given name
+    pub fn has_tileset_named(&self, name: &str) -> bool {
+        self.tilesets.iter().any(|set| set.name == name)
+    }
+    
+    /// The tile layers, object layers and image layers of this level, each in
+    /// layer order. Any new kinds of layer will need adding here.
+    pub fn split_by_layer_type(&self) -> (Vec<&TileLayer>, Vec<&ObjectLayer>, Vec<&ImageLayer>) {
+        let mut tiles = Vec::new();
+        let mut objects = Vec::new();
+        let mut images = Vec::new();
+        for layer in &self.layers {
+            match *layer {
+                Layer::Tiles(ref layer) => tiles.push(layer),
+                Layer::Objects(ref layer) => objects.push(layer),
+                Layer::Images(ref layer) => images.push(layer),
+            }
+        }
+        (tiles, objects, images)
+    }
+    
+    /// Mutable version of `split_by_layer_type`
+    pub fn split_by_layer_type_mut(&mut self) -> (Vec<&mut TileLayer>, Vec<&mut ObjectLayer>, Vec<&mut ImageLayer>) {
+        let mut tiles = Vec::new();
+        let mut objects = Vec::new();
+        let mut images = Vec::new();
+        for layer in &mut self.layers {
+            match *layer {
+                Layer::Tiles(ref mut layer) => tiles.push(layer),
+                Layer::Objects(ref mut layer) => objects.push(layer),
+                Layer::Images(ref mut layer) => images.push(layer),
+            }
+        }
+        (tiles, objects, images)
+    }
+    
+    /// The objects of every object layer grouped by type. Each group has the
+    /// objects of the bottom layer first, each layer's objects in its draw
+    /// order. Objects with no type are grouped under the empty string.
+    pub fn objects_by_type(&self) -> HashMap<&str, Vec<&Object>> {
+        self.objects_by_type_in_order(ObjectOrder::DrawOrder)
+    }
+    
+    /// Like `objects_by_type`, with each layer's objects in the given order
+    pub fn objects_by_type_in_order(&self, order: ObjectOrder) -> HashMap<&str, Vec<&Object>> {
+        let mut groups: HashMap<&str, Vec<&Object>> = HashMap::new();
+        for object in self.object_layers().flat_map(|layer| layer.objects_in_order(order)) {
+            groups.entry(&object.kind[..]).or_default().push(object);
+        }
+        groups
+    }
+    
+    /// Flattens the tile layers into one layer the size of the level, where
+    /// each cell has the tile from the topmost layer with a tile there. Fails
+    /// with `NoLayers` if there are no tile layers, or `DimensionMismatch` if
+    /// one isn't the size of the level.
+    pub fn overlay_tile_layers(&self) -> Result<TileLayer, TiledError> {
+        if self.tile_layers().next().is_none() {
+            return Err(TiledError::NoLayers);
+        }
+        
+        let empty = TileLayer::generate_from("overlay", self.width, self.height, |_, _| GlobalTile::EMPTY);
+        // Later layers are drawn on top of earlier ones
+        self.tile_layers().try_fold(empty, |below, layer| {
+            below.zip_with(layer, |below, above| if above.is_empty() { below } else { above })
+        })
+    }
+    
+    /// Mirrors the whole map left to right: every tile layer is flipped with
+    /// `TileLayer::flip_horizontal`, and every object is reflected across the
+    /// middle of the map, including its rotation, polygon and tile. Object
+    /// positions are mirrored in pixels, which is only right for orthogonal
+    /// maps. Image layers are left as they are, since their image can't be
+    /// flipped.
+    pub fn mirror_horizontal(&mut self) {
+        let width = self.pixel_size().0 as f32;
+        for layer in &mut self.layers {
+            match *layer {
+                Layer::Tiles(ref mut tiles) => tiles.flip_horizontal(),
+                Layer::Objects(ref mut objects) => objects.transform_all(|object| object.mirror_horizontal(width)),
+                Layer::Images(_) => {}
+            }
+        }
+    }
+    
+    /// Mirrors the whole map top to bottom, like `mirror_horizontal`
+    pub fn mirror_vertical(&mut self) {
+        let height = self.pixel_size().1 as f32;
+        for layer in &mut self.layers {
+            match *layer {
+                Layer::Tiles(ref mut tiles) => tiles.flip_vertical(),
+                Layer::Objects(ref mut objects) => objects.transform_all(|object| object.mirror_vertical(height)),
+                Layer::Images(_) => {}
+            }
+        }
+    }
+    
+    /// Number of cells without a tile, summed across all tile layers
+    pub fn count_empty_tiles(&self) -> u64 {
+        self.tile_layers()
+            .map(|layer| layer.data.iter().filter(|tile| tile.is_empty()).count() as u64)
+            .sum()
+    }
+    
+    /// Number of cells containing a tile, summed across all tile layers
+    pub fn count_non_empty_tiles(&self) -> u64 {
+        self.tile_layers()
+            .map(|layer| layer.data.iter().filter(|tile| !tile.is_empty()).count() as u64)
+            .sum()
+    }
+    
+    /// The number of objects in all object layers
+    pub fn total_object_count(&self) -> usize {
+        self.object_layers().map(|layer| layer.objects.len()).sum()
+    }
+    
+    /// `count_non_empty_tiles`, named to go with `total_object_count` for
+    /// summaries of a level's size
+    pub fn total_non_empty_tile_count(&self) -> u64 {
+        self.count_non_empty_tiles()
+    }
+    
+    /// The fraction of cells which contain a tile across all tile layers, where
+    /// the total number of cells is `tile layers * width * height`. A level
+    /// with no cells has a density of 0.
+    pub fn tile_density(&self) -> f64 {
+        let cells = self.tile_layers().count() as u64 * self.width as u64 * self.height as u64;
+        if cells == 0 {
+            return 0.0;
+        }
+        self.count_non_empty_tiles() as f64 / cells as f64
+    }
+    
+    /// The canonical gid of every tile shown in the level, by tile layers or
+    /// by tile objects. Flipped and unflipped uses of a tile count as one.
+    pub fn all_tile_gids(&self) -> HashSet<GlobalTile> {
+        let layer_gids = self.tile_layers().flat_map(|layer| layer.data.iter().cloned());
+        let object_gids = self.object_layers()
+            .flat_map(|layer| layer.objects.iter())
+            .filter_map(|object| object.gid);
+        layer_gids.chain(object_gids)
+            .filter(|gid| !gid.is_empty())
+            .map(GlobalTile::canonical)
+            .collect()
+    }
+    
+    /// How much of each tileset the level uses, as `(name, tiles used,
+    /// percentage of its tiles used)` in tileset order, for finding tilesets
+    /// which could be merged or removed. Unused tilesets are included with a
+    /// count of 0, and gids which don't belong to a tileset are ignored.
+    pub fn compute_tileset_coverage(&self) -> Vec<(String, u32, f64)> {
+        let mut used = vec![0u32; self.tilesets.len()];
+        for gid in self.all_tile_gids() {
+            if let Some((i, _)) = gid.find_local(&self.tilesets) {
+                used[i] += 1;
+            }
+        }
+        
+        self.tilesets.iter().zip(used).map(|(set, used)| {
+            let percentage = if set.tilecount == 0 { 0.0 } else { used as f64 * 100.0 / set.tilecount as f64 };
+            (set.name.clone(), used, percentage)
+        }).collect()
+    }
+    
+    /// The names of the top-level layers, in order
+    pub fn layer_names(&self) -> Vec<&str> {
+        self.layers.iter().map(|layer| layer.name()).collect()
+    }
+    
+    /// Checks that the top-level layers are exactly the ones named in
+    /// `expected`, in that order, such as for enforcing a project's map
+    /// layout in asset checks
+    pub fn assert_layer_order(&self, expected: &[&str]) -> Result<(), TiledError> {
+        let actual = self.layer_names();
+        if actual == expected {
+            return Ok(());
+        }
+        Err(TiledError::LayerOrderViolation {
+            expected: expected.iter().map(|name| name.to_string()).collect(),
+            actual: actual.iter().map(|name| name.to_string()).collect(),
+        })
+    }
+    
+    /// Whether any two top-level layers share a name
+    pub fn has_duplicate_layer_names(&self) -> bool {
+        let names = self.layer_names();
+        names.iter().enumerate().any(|(i, name)| names[i + 1..].contains(name))
+    }
+    
+    /// The names shared by more than one top-level layer, each listed once in
+    /// the order they first appear. Tiled allows duplicate names, but lookups
+    /// by name can then find the wrong layer.
+    pub fn duplicate_layer_names(&self) -> Vec<String> {
+        let mut duplicates: Vec<String> = Vec::new();
+        for (i, layer) in self.layers.iter().enumerate() {
+            let name = layer.name();
+            let repeated = self.layers[i + 1..].iter().any(|other| other.name() == name);
+            if repeated && !duplicates.iter().any(|dup| dup == name) {
+                duplicates.push(name.to_string());
+            }
+        }
+        duplicates
+    }
+    
+    /// Renames the top-level layer called `old` to `new`. Fails if no layer
+    /// or more than one layer is called `old`, or if another layer is already
+    /// called `new`.
+    pub fn rename_layer(&mut self, old: &str, new: &str) -> Result<(), TiledError> {
+        let index = self.unique_layer_index(old)?;
+        if old != new && self.layers.iter().any(|layer| layer.name() == new) {
+            return Err(TiledError::LayerNameTaken(new.to_string()));
+        }
+        
+        self.layers[index].set_name(new);
+        Ok(())
+    }
+    
+    /// The index in `layers` of the first top-level layer with the given name
+    pub fn find_layer_index_by_name(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name() == name)
+    }
+    
+    /// Inserts `layer` just above the top-level layer called `after`, i.e.
+    /// drawn on top of it. Fails if no layer or more than one layer is called
+    /// `after`.
+    pub fn insert_layer_after_name(&mut self, after: &str, layer: Layer) -> Result<(), TiledError> {
+        let index = self.unique_layer_index(after)?;
+        self.layers.insert(index + 1, layer);
+        Ok(())
+    }
+    
+    /// Inserts `layer` just below the top-level layer called `before`, i.e.
+    /// drawn underneath it. Fails if no layer or more than one layer is
+    /// called `before`.
+    pub fn insert_layer_before_name(&mut self, before: &str, layer: Layer) -> Result<(), TiledError> {
+        let index = self.unique_layer_index(before)?;
+        self.layers.insert(index, layer);
+        Ok(())
+    }
+    
+    /// The index of the only top-level layer called `name`
+    fn unique_layer_index(&self, name: &str) -> Result<usize, TiledError> {
+        let mut matching = self.layers.iter().enumerate().filter(|&(_, layer)| layer.name() == name);
+        match (matching.next(), matching.next()) {
+            (Some((i, _)), None) => Ok(i),
+            (None, _) => Err(TiledError::LayerNotFound(name.to_string())),
+            (Some(_), Some(_)) => Err(TiledError::AmbiguousLayerName(name.to_string())),
+        }
+    }
+    
+    /// Removes the first top-level layer with the given name. Prefer
+    /// `remove_layer_by_id` when layers may share a name.
+    pub fn remove_layer_by_name(&mut self, name: &str) -> Option<Layer> {
+        match self.find_layer_index_by_name(name) {
+            Some(i) => Some(self.layers.remove(i)),
+            None => None,
+        }
+    }
+    
+    /// Removes the top-level layer with the given ID. Layer IDs are unique
+    /// within a map, so this is the reliable way to remove a specific layer.
+    pub fn remove_layer_by_id(&mut self, id: u32) -> Option<Layer> {
+        match self.layers.iter().position(|layer| layer.id() == Some(id)) {
+            Some(i) => Some(self.layers.remove(i)),
+            None => None,
+        }
+    }
+    
+    /// Reorders the top-level layers to follow the names in `new_order`.
+    /// Layers not named there keep their relative order after the ones that
+    /// are, and layers sharing a name stay together in their original order.
+    /// Fails without changing anything if a name doesn't match any layer.
+    pub fn reorder_layers(&mut self, new_order: &[&str]) -> Result<(), TiledError> {
+        for &name in new_order {
+            if !self.layers.iter().any(|layer| layer.name() == name) {
+                return Err(TiledError::LayerNotFound(name.to_string()));
+            }
+        }
+        
+        // The sort is stable, so only the named layers change position
+        self.layers.sort_by_key(|layer| {
+            new_order.iter().position(|&name| name == layer.name()).unwrap_or(new_order.len())
+        });
+        Ok(())
+    }
+    
+    /// The top-level layers a game should use: those which are visible and
+    /// not locked. Locked and hidden layers are often editor guides or notes.
+    pub fn runtime_layers(&self) -> impl Iterator<Item = &Layer> {
+        self.layers.iter().filter(|layer| layer.visible() && !layer.locked())
+    }
+    
+    /// Removes every top-level layer which is locked, returning the removed
+    /// layers in their original order
+    pub fn strip_locked_layers(&mut self) -> Vec<Layer> {
+        let layers = ::std::mem::take(&mut self.layers);
+        let (unlocked, locked) = layers.into_iter().partition(|layer| !layer.locked());
+        self.layers = unlocked;
+        locked
+    }
+    
+    /// Removes every top-level layer which is hidden, returning the removed
+    /// layers in their original order. Useful for leaving editor-only layers
+    /// out of shipped maps.
+    pub fn strip_invisible_layers(&mut self) -> Vec<Layer> {
+        let layers = ::std::mem::take(&mut self.layers);
+        let (visible, hidden) = layers.into_iter().partition(|layer| layer.visible());
+        self.layers = visible;
+        hidden
+    }
+    
+    /// Gives every object in the level a new id, counting up from `start_id`
+    /// in layer order and keeping the order of objects within each layer, then
+    /// updates `nextobjectid` to follow them. Use this before saving merged or
+    /// imported maps so that object ids don't conflict in Tiled.
+    ///
+    /// Object-typed properties are updated to the new ids. Where an old id
+    /// was shared by several objects, references to it go to the first one.
+    /// Fails with `ObjectIdsExhausted`, leaving the level unchanged, if the
+    /// new ids wouldn't fit after `start_id`.
+    pub fn renumber_objects(&mut self, start_id: u32) -> Result<(), TiledError> {
+        let count = self.object_layers().map(|layer| layer.objects.len() as u64).sum::<u64>();
+        // The new ids and the `nextobjectid` after them all have to fit
+        if start_id as u64 + count > u32::MAX as u64 {
+            return Err(TiledError::ObjectIdsExhausted);
+        }
+        
+        let mut ids = HashMap::new();
+        let mut next_id = start_id;
+        for layer in &mut self.layers {
+            if let Layer::Objects(ref mut objects) = *layer {
+                for object in &mut objects.objects {
+                    ids.entry(object.id).or_insert(next_id);
+                    object.id = next_id;
+                    next_id += 1;
+                }
+            }
+        }
+        self.nextobjectid = next_id;
+        self.remap_object_refs(&ids);
+        Ok(())
+    }
+    
+    /// Ids which are used by more than one object, in ascending order. Tiled
+    /// never writes these, but merging maps or other tools can.
+    pub fn duplicate_object_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.object_layers().flat_map(|layer| &layer.objects).map(|o| o.id).collect();
+        ids.sort();
+        let mut duplicates: Vec<u32> = ids.windows(2).filter(|pair| pair[0] == pair[1]).map(|pair| pair[0]).collect();
+        duplicates.dedup();
+        duplicates
+    }
+    
+    /// Makes every object id unique. The first object with each id, in layer
+    /// order, keeps it and later ones are given new ids after `nextobjectid`,
+    /// which is then updated. Since ids that were already in use stay with the
+    /// same object, object-typed properties keep pointing where they did;
+    /// references to a duplicated id point at the first object with it.
+    /// Returns the number of objects given a new id, or fails with
+    /// `ObjectIdsExhausted`, leaving the level unchanged, if the new ids
+    /// wouldn't fit.
+    pub fn reassign_object_ids(&mut self) -> Result<usize, TiledError> {
+        let mut seen = HashSet::new();
+        let duplicates = self.object_layers().flat_map(|layer| &layer.objects)
+            .filter(|o| !seen.insert(o.id))
+            .count();
+        let max_id = self.object_layers().flat_map(|layer| &layer.objects).map(|o| o.id).max();
+        let mut next_id = max(self.nextobjectid, max_id.map_or(1, |id| id.saturating_add(1)));
+        // The new ids and the `nextobjectid` after them all have to fit
+        if duplicates > 0 && next_id as u64 + duplicates as u64 > u32::MAX as u64 {
+            return Err(TiledError::ObjectIdsExhausted);
+        }
+        
+        seen.clear();
+        let mut reassigned = 0;
+        for layer in &mut self.layers {
+            if let Layer::Objects(ref mut objects) = *layer {
+                for object in &mut objects.objects {
+                    if !seen.insert(object.id) {
+                        object.id = next_id;
+                        next_id += 1;
+                        reassigned += 1;
+                    }
+                }
+            }
+        }
+        self.nextobjectid = next_id;
+        Ok(reassigned)
+    }
+    
+    /// Rewrites object-typed properties throughout the level using `ids`,
+    /// which maps old object ids to new ones
+    fn remap_object_refs(&mut self, ids: &HashMap<u32, u32>) {
+        self.properties.remap_object_refs(ids);
+        for layer in &mut self.layers {
+            match *layer {
+                Layer::Tiles(ref mut tiles) => tiles.properties.remap_object_refs(ids),
+                Layer::Images(ref mut image) => image.properties.remap_object_refs(ids),
+                Layer::Objects(ref mut objects) => {
+                    objects.properties.remap_object_refs(ids);
+                    for object in &mut objects.objects {
+                        object.properties.remap_object_refs(ids);
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Iterates every custom property in the level along with what it belongs to.
+    /// The order is deterministic: map properties first, then each layer
+    /// followed by the objects in it, then each tileset followed by its tiles
+    /// in id order. Properties of a single owner are sorted by key.
+    pub fn iter_all_properties(&self) -> impl Iterator<Item = PropertyRef<'_>> {
+        fn push_sorted<'a>(all: &mut Vec<PropertyRef<'a>>, owner: PropertyOwner<'a>, props: &'a Properties) {
+            all.extend(props.iter().map(|(key, value)| PropertyRef {
+                owner,
+                key,
+                value,
+            }));
+        }
+        
+        let mut all = Vec::new();
+        push_sorted(&mut all, PropertyOwner::Map, &self.properties);
+        
+        for layer in &self.layers {
+            match *layer {
+                Layer::Tiles(ref tiles) => {
+                    push_sorted(&mut all, PropertyOwner::Layer(&tiles.name), &tiles.properties);
+                }
+                Layer::Images(ref image) => {
+                    push_sorted(&mut all, PropertyOwner::Layer(&image.name), &image.properties);
+                }
+                Layer::Objects(ref objects) => {
+                    push_sorted(&mut all, PropertyOwner::Layer(&objects.name), &objects.properties);
+                    for object in &objects.objects {
+                        let owner = PropertyOwner::Object { layer: &objects.name, id: object.id };
+                        push_sorted(&mut all, owner, &object.properties);
+                    }
+                }
+            }
+        }
+        
+        for set in &self.tilesets {
+            push_sorted(&mut all, PropertyOwner::Tileset(&set.name), &set.properties);
+            
+            let mut tiles: Vec<_> = set.tileproperties.tiles.iter().collect();
+            tiles.sort_by_key(|&(tile, _)| tile.0);
+            for (&tile, props) in tiles {
+                push_sorted(&mut all, PropertyOwner::Tile { tileset: &set.name, tile }, props);
+            }
+        }
+        
+        all.into_iter()
+    }
+    
+    /// The properties of the object with the given id, with properties it
+    /// doesn't set taken from its layer, and ones neither set taken from the
+    /// map. None if there is no such object.
+    pub fn property_chain_for_object(&self, id: u32) -> Option<Properties> {
+        for layer in self.object_layers() {
+            if let Some(object) = layer.objects.iter().find(|object| object.id == id) {
+                let inherited = layer.properties.merged_over(&self.properties);
+                return Some(object.properties.merged_over(&inherited));
+            }
+        }
+        None
+    }
+    
+    /// Calls `f` with every custom property in the level, allowing the value to
+    /// be rewritten in place. Properties are visited in the same order as
+    /// `iter_all_properties`. A property keeps the type it was declared with,
+    /// so a rewrite which doesn't parse as that type (see `get_value`) is
+    /// undone and a warning is recorded.
+    pub fn map_properties<F>(&mut self, mut f: F)
+        where F: FnMut(PropertyOwner, &str, &mut String)
+    {
+        let mut rejected = Vec::new();
+        self.visit_properties_mut(|owner, properties| {
+            let keys: Vec<String> = properties.keys().cloned().collect();
+            for key in keys {
+                let old = properties[&key].clone();
+                let mut value = old.clone();
+                f(owner, &key, &mut value);
+                if value == old {
+                    continue;
+                }
+                
+                properties.replace_value(&key, value);
+                if let Some(kind) = properties.type_mismatch(&key) {
+                    rejected.push(format!(
+                        "Property `{}` of {} can't be `{}` as it is declared as {}, keeping `{}`",
+                        key, owner, properties[&key], kind, old
+                    ));
+                    properties.replace_value(&key, old);
+                }
+            }
+        });
+        self.warnings.extend(rejected);
+    }
+    
+    /// Calls `f` with the properties of everything in the level which has
+    /// them, in the same order as `iter_all_properties`
+    fn visit_properties_mut<F>(&mut self, mut f: F)
+        where F: FnMut(PropertyOwner, &mut Properties)
+    {
+        f(PropertyOwner::Map, &mut self.properties);
+        
+        for layer in &mut self.layers {
+            match *layer {
+                Layer::Tiles(TileLayer { ref name, ref mut properties, .. }) |
+                Layer::Images(ImageLayer { ref name, ref mut properties, .. }) => {
+                    f(PropertyOwner::Layer(name), properties);
+                }
+                Layer::Objects(ObjectLayer { ref name, ref mut properties, ref mut objects, .. }) => {
+                    f(PropertyOwner::Layer(name), properties);
+                    for object in objects {
+                        f(PropertyOwner::Object { layer: name, id: object.id }, &mut object.properties);
+                    }
+                }
+            }
+        }
+        
+        for set in &mut self.tilesets {
+            // The visitor works on copies, so that a tileset shared with
+            // clones of the level is only copied if its properties change
+            let mut properties = set.properties.clone();
+            let mut tileproperties = set.tileproperties.clone();
+            f(PropertyOwner::Tileset(&set.name), &mut properties);
+            
+            let mut tiles: Vec<_> = tileproperties.tiles.iter_mut().collect();
+            tiles.sort_by_key(|&(tile, _)| tile.0);
+            for (&tile, props) in tiles {
+                f(PropertyOwner::Tile { tileset: &set.name, tile }, props);
+            }
+            
+            if properties != set.properties || tileproperties != set.tileproperties {
+                let set = Arc::make_mut(set);
+                set.properties = properties;
+                set.tileproperties = tileproperties;
+            }
+        }
+    }
+    
+    /// Merges tilesets which have the same name and image into the first one
+    /// listed, remapping every gid which used a duplicate to the matching tile
+    /// of the tileset it was merged into. Returns the number of tilesets removed.
+    ///
+    /// Tilesets are only merged if their tiles are the same, so they must
+    /// also have the same grid (see `Tileset::same_grid`), tile properties
+    /// and terrains.
+    pub fn compress_tilesets(&mut self) -> usize {
+        // (first gid, tile count, first gid of canonical tileset) for each duplicate
+        let mut remaps = Vec::new();
+        let mut duplicates = Vec::new();
+        for (i, set) in self.tilesets.iter().enumerate() {
+            // The first matching tileset can't be a duplicate itself
+            let canonical = self.tilesets[..i].iter()
+                .find(|other| {
+                    other.name == set.name && other.image == set.image && other.same_grid(set)
+                        && other.tileproperties == set.tileproperties && other.tiles == set.tiles
+                });
+            if let Some(canonical) = canonical {
+                remaps.push((set.firstgid.0, set.tilecount, canonical.firstgid.0));
+                duplicates.push(i);
+            }
+        }
+        
+        if duplicates.is_empty() {
+            return 0;
+        }
+        
+        self.map_gids(|gid| {
+            let id = gid.strip_flags().0;
+            for &(first, count, canonical) in &remaps {
+                if id >= first && id - first < count {
+                    return GlobalTile((id - first + canonical) | (gid.0 & FLIP_FLAGS));
+                }
+            }
+            gid
+        });
+        
+        for &i in duplicates.iter().rev() {
+            self.tilesets.remove(i);
+        }
+        duplicates.len()
+    }
+    
+    /// Replaces every gid in the level, in tile layers and tile objects, with
+    /// the result of `f`. Empty cells and objects without a gid are skipped,
+    /// and tile objects given an empty gid stop being tile objects.
+    fn map_gids<F>(&mut self, mut f: F) where F: FnMut(GlobalTile) -> GlobalTile {
+        for layer in &mut self.layers {
+            match *layer {
+                Layer::Tiles(ref mut tiles) => {
+                    for tile in tiles.data.iter_mut().filter(|tile| !tile.is_empty()) {
+                        *tile = f(*tile);
+                    }
+                }
+                Layer::Objects(ref mut objects) => {
+                    for object in &mut objects.objects {
+                        object.gid = object.gid.map(&mut f).and_then(|gid| if gid.is_empty() { None } else { Some(gid) });
+                    }
+                }
+                Layer::Images(_) => {}
+            }
+        }
+    }
+    
+    /// Stamps `patch` onto this level with its top-left corner at tile `dst`.
+    ///
+    /// - Tile layers are copied onto the first tile layer with the same name,
+    ///   which is created if there isn't one. Empty cells of the patch leave
+    ///   the tile below them alone, and tiles outside this level are dropped.
+    /// - Objects are added to the first object layer with the same name, or a
+    ///   new one, with fresh ids and positions moved by `dst`. Object-typed
+    ///   properties which refer to objects of the patch are updated to the
+    ///   fresh ids.
+    /// - Image layers are copied, with their offset moved by `dst`, unless
+    ///   this level has an image layer with the same name already.
+    /// - The patch's tilesets are matched to this level's by name, image and
+    ///   grid, and the ones which aren't found are added after the existing
+    ///   ones. Gids from the patch are remapped to match. A tile whose gid
+    ///   isn't in any of the patch's tilesets is skipped, and an object with
+    ///   such a gid is copied without one, recording a warning either way.
+    /// - Layer properties from the patch are added, but where both layers have
+    ///   a property with different values this level's is kept and a warning
+    ///   is recorded.
+    ///
+    /// Fails, leaving this level unchanged, if the levels have different tile
+    /// sizes, or if the patch's tilesets or objects can't be given gids or ids
+    /// which fit.
+    pub fn overlay(&mut self, patch: &Level, dst: TileCoord) -> Result<(), TiledError> {
+        if (self.tilewidth, self.tileheight) != (patch.tilewidth, patch.tileheight) {
+            return Err(TiledError::TileSizeMismatch {
+                base: (self.tilewidth, self.tileheight),
+                other: (patch.tilewidth, patch.tileheight),
+            });
+        }
+        
+        // (first gid, tile count, first gid in this level) for each patch tileset
+        let mut remaps = Vec::new();
+        let mut added_sets = Vec::new();
+        let mut next_gid = self.next_firstgid();
+        for set in &patch.tilesets {
+            let existing = self.tilesets.iter()
+                .find(|other| other.name == set.name && other.image == set.image && other.same_grid(set))
+                .map(|other| other.firstgid.0);
+            let firstgid = match existing {
+                Some(firstgid) => firstgid,
+                None => {
+                    let firstgid = next_gid;
+                    // Only copied if the firstgid has to change
+                    let mut added = set.clone();
+                    if added.firstgid.0 != firstgid {
+                        Arc::make_mut(&mut added).firstgid = GlobalTile(firstgid);
+                    }
+                    // The tiles have to fit below the flip flags, which also
+                    // means the next firstgid can't overflow
+                    if let Some(problem) = added.firstgid_problem() {
+                        return Err(TiledError::InvalidTileset(vec![problem]));
+                    }
+                    added_sets.push(added);
+                    next_gid = firstgid + set.tilecount;
+                    firstgid
+                }
+            };
+            remaps.push((set.firstgid.0, set.tilecount, firstgid));
+        }
+        let remap = |gid: GlobalTile| {
+            let id = gid.strip_flags().0;
+            for &(first, count, target) in &remaps {
+                if id >= first && id - first < count {
+                    return Some(GlobalTile((id - first + target) | (gid.0 & FLIP_FLAGS)));
+                }
+            }
+            None
+        };
+        
+        // The copies are numbered in the order they are added. Where an id
+        // was shared by several patch objects, references go to the first.
+        let mut ids = HashMap::new();
+        let mut next_id = self.nextobjectid as u64;
+        for object in patch.object_layers().flat_map(|layer| &layer.objects) {
+            ids.entry(object.id).or_insert(next_id as u32);
+            next_id += 1;
+            // The `nextobjectid` after the copies has to fit too
+            if next_id > u32::MAX as u64 {
+                return Err(TiledError::ObjectIdsExhausted);
+            }
+        }
+        self.tilesets.extend(added_sets);
+        
+        let offset_x = dst.x as f32 * self.tilewidth as f32;
+        let offset_y = dst.y as f32 * self.tileheight as f32;
+        for patch_layer in &patch.layers {
+            let index = self.layers.iter().position(|layer| match (layer, patch_layer) {
+                (Layer::Tiles(a), Layer::Tiles(b)) => a.name == b.name,
+                (Layer::Objects(a), Layer::Objects(b)) => a.name == b.name,
+                (Layer::Images(a), Layer::Images(b)) => a.name == b.name,
+                _ => false,
+            });
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    self.layers.push(match *patch_layer {
+                        Layer::Tiles(ref tiles) => Layer::Tiles(TileLayer {
+                            name: tiles.name.clone(),
+                            width: self.width,
+                            height: self.height,
+                            data: vec![GlobalTile::EMPTY; self.width as usize * self.height as usize],
+                            ..TileLayer::EMPTY
+                        }),
+                        Layer::Objects(ref objects) => Layer::Objects(ObjectLayer {
+                            objects: Vec::new(),
+                            properties: Properties::new(),
+                            source_index: None,
+                            ..objects.clone()
+                        }),
+                        Layer::Images(ref image) => Layer::Images(ImageLayer {
+                            offsetx: Some(image.offsetx.unwrap_or(0.0) + offset_x),
+                            offsety: Some(image.offsety.unwrap_or(0.0) + offset_y),
+                            properties: Properties::new(),
+                            source_index: None,
+                            ..image.clone()
+                        }),
+                    });
+                    self.layers.len() - 1
+                }
+            };
+            
+            let (base_properties, patch_properties) = match (&mut self.layers[index], patch_layer) {
+                (&mut Layer::Tiles(ref mut base), Layer::Tiles(patch)) => {
+                    for y in 0..patch.height {
+                        for x in 0..patch.width {
+                            let (bx, by) = (dst.x + x, dst.y + y);
+                            if bx >= base.width || by >= base.height {
+                                continue;
+                            }
+                            match patch.tile_at(x, y).map(|tile| (tile, remap(tile))) {
+                                Some((tile, _)) if tile.is_empty() => {}
+                                Some((_, Some(remapped))) => {
+                                    base.data[by as usize * base.width as usize + bx as usize] = remapped;
+                                }
+                                Some((tile, None)) => self.warnings.push(format!(
+                                    "Layer `{}` tile ({}, {}): gid {} isn't in any of the patch's tilesets, so it was left out",
+                                    patch.name, x, y, tile.0
+                                )),
+                                None => {}
+                            }
+                        }
+                    }
+                    (&mut base.properties, &patch.properties)
+                }
+                (&mut Layer::Objects(ref mut base), Layer::Objects(patch)) => {
+                    for object in &patch.objects {
+                        let mut object = object.clone();
+                        object.id = self.nextobjectid;
+                        self.nextobjectid += 1;
+                        object.x += offset_x;
+                        object.y += offset_y;
+                        object.gid = match object.gid {
+                            Some(gid) if !gid.is_empty() => {
+                                let remapped = remap(gid);
+                                if remapped.is_none() {
+                                    self.warnings.push(format!(
+                                        "Layer `{}` object {}: gid {} isn't in any of the patch's tilesets, so it was left out",
+                                        patch.name, object.id, gid.0
+                                    ));
+                                }
+                                remapped
+                            }
+                            gid => gid,
+                        };
+                        object.properties.remap_object_refs(&ids);
+                        object.source_index = None;
+                        base.objects.push(object);
+                    }
+                    (&mut base.properties, &patch.properties)
+                }
+                (&mut Layer::Images(ref mut base), Layer::Images(patch)) => {
+                    (&mut base.properties, &patch.properties)
+                }
+                _ => unreachable!(),
+            };
+            
+            let mut patch_properties = patch_properties.clone();
+            patch_properties.remap_object_refs(&ids);
+            for (key, value) in patch_properties.iter() {
+                match base_properties.get(key).cloned() {
+                    Some(ref existing) if existing != value => self.warnings.push(format!(
+                        "Layer `{}` property `{}` is `{}` in the patch, keeping `{}`",
+                        patch_layer.name(), key, value, existing
+                    )),
+                    Some(_) => {}
+                    None => base_properties.copy_from(&patch_properties, key),
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Changes the part of the level loaded from `pointer` in its JSON, such
+    /// as a field an editor plugin changed, without reloading the level. The
+    /// level must have been loaded with `LoadOptions::record_source_pointers`,
+    /// and pointers refer to the JSON as loaded even if layers have been
+    /// moved since. The fields which can be patched are:
+    ///
+    /// - `/properties/<key>`, and the same under a layer, object or tileset,
+    ///   keeping the property's type. Values other than strings are stored
+    ///   as their JSON text, as when loading.
+    /// - `/layers/<i>/visible`, `opacity`, `offsetx` and `offsety`
+    /// - `/layers/<i>/objects/<j>/x`, `y`, `width`, `height`, `rotation` and
+    ///   `visible`
+    ///
+    /// `~1` and `~0` in property keys stand for `/` and `~`, as in any JSON
+    /// pointer.
+    pub fn apply_json_patch(&mut self, pointer: &str, value: JsonValue) -> Result<(), TiledError> {
+        let error = |reason: &str| TiledError::InvalidPatch { pointer: pointer.to_string(), reason: reason.to_string() };
+        let unsupported = || error("only positions, visibility, opacity and properties can be patched");
+        let not_loaded = || error("nothing in the level was loaded from there; load it with `LoadOptions::record_source_pointers`");
+        let wrong_type = || error("the value has the wrong type for the field");
+        
+        if !pointer.starts_with('/') {
+            return Err(unsupported());
+        }
+        let parts: Vec<String> = pointer[1..].split('/').map(|part| part.replace("~1", "/").replace("~0", "~")).collect();
+        let parts: Vec<&str> = parts.iter().map(|part| &part[..]).collect();
+        let (field, path) = match parts.split_last() {
+            Some(split) => split,
+            None => return Err(unsupported()),
+        };
+        let (is_property, path) = match path.split_last() {
+            Some((&"properties", path)) => (true, path),
+            _ => (false, path),
+        };
+        let index = |part: &str| part.parse::<usize>().ok();
+        
+        match *path {
+            [] if is_property => {
+                self.properties.replace_value(field, property_text(value));
+            }
+            ["tilesets", i] if is_property => {
+                let set = match self.tilesets.iter_mut().find(|set| set.source_index.is_some() && set.source_index == index(i)) {
+                    Some(set) => Arc::make_mut(set),
+                    None => return Err(not_loaded()),
+                };
+                set.properties.replace_value(field, property_text(value));
+            }
+            ["layers", i] => {
+                let layer = match self.layers.iter_mut().find(|layer| layer.source_index().is_some() && layer.source_index() == index(i)) {
+                    Some(layer) => layer,
+                    None => return Err(not_loaded()),
+                };
+                let (properties, visible, opacity, offsetx, offsety) = match *layer {
+                    Layer::Tiles(TileLayer { ref mut properties, ref mut visible, ref mut opacity, ref mut offsetx, ref mut offsety, .. }) |
+                    Layer::Objects(ObjectLayer { ref mut properties, ref mut visible, ref mut opacity, ref mut offsetx, ref mut offsety, .. }) |
+                    Layer::Images(ImageLayer { ref mut properties, ref mut visible, ref mut opacity, ref mut offsetx, ref mut offsety, .. }) => {
+                        (properties, visible, opacity, offsetx, offsety)
+                    }
+                };
+                if is_property {
+                    properties.replace_value(field, property_text(value));
+                    return Ok(());
+                }
+                match (*field, value.as_f64(), value.as_bool()) {
+                    ("visible", _, Some(flag)) => *visible = flag,
+                    ("opacity", Some(n), _) => *opacity = n as f32,
+                    ("offsetx", Some(n), _) => *offsetx = Some(n as f32),
+                    ("offsety", Some(n), _) => *offsety = Some(n as f32),
+                    ("visible", ..) | ("opacity", ..) | ("offsetx", ..) | ("offsety", ..) => return Err(wrong_type()),
+                    _ => return Err(unsupported()),
+                }
+            }
+            ["layers", i, "objects", j] => {
+                let source = match (index(i), index(j)) {
+                    (Some(i), Some(j)) => Some((i, j)),
+                    _ => return Err(not_loaded()),
+                };
+                // Objects are looked for in every layer, since they may have
+                // been moved out of the one they were loaded in
+                let object = self.layers.iter_mut()
+                    .filter_map(|layer| match *layer {
+                        Layer::Objects(ref mut objects) => Some(objects),
+                        _ => None,
+                    })
+                    .flat_map(|layer| layer.objects.iter_mut())
+                    .find(|object| object.source_index == source);
+                let object = match object {
+                    Some(object) => object,
+                    None => return Err(not_loaded()),
+                };
+                if is_property {
+                    object.properties.replace_value(field, property_text(value));
+                    return Ok(());
+                }
+                match (*field, value.as_f64(), value.as_bool()) {
+                    ("x", Some(n), _) => object.x = n as f32,
+                    ("y", Some(n), _) => object.y = n as f32,
+                    ("width", Some(n), _) => object.width = n as f32,
+                    ("height", Some(n), _) => object.height = n as f32,
+                    ("rotation", Some(n), _) => object.rotation = n as f32,
+                    ("visible", _, Some(flag)) => object.visible = flag,
+                    ("x", ..) | ("y", ..) | ("width", ..) | ("height", ..) | ("rotation", ..) | ("visible", ..) => {
+                        return Err(wrong_type());
+                    }
+                    _ => return Err(unsupported()),
+                }
+            }
+            _ => return Err(unsupported()),
+        }
+        Ok(())
+    }
+    
+    /// Mutable access to tileset `index`. If the tileset is shared with a
+    /// clone of this level it is copied first, so the clone isn't changed.
+    pub fn tileset_mut(&mut self, index: usize) -> Option<&mut Tileset> {
+        self.tilesets.get_mut(index).map(Arc::make_mut)
+    }
+    
+    /// Adds a tileset to the end of the list. `firstgid` must already be set
+    /// to follow the level's other tilesets.
+    pub fn push_tileset(&mut self, tileset: Tileset) {
+        self.tilesets.push(Arc::new(tileset));
+    }
+    
+    /// Adds a tileset to the end of the list with its `firstgid` set to just
+    /// after the tiles of the level's other tilesets, so no gids already in
+    /// the level change meaning. Returns the index of the new tileset, or
+    /// fails with `InvalidTileset` if its tiles' gids wouldn't fit below the
+    /// flip flags.
+    pub fn add_tileset(&mut self, mut tileset: Tileset) -> Result<usize, TiledError> {
+        tileset.firstgid = GlobalTile(self.next_firstgid());
+        if let Some(problem) = tileset.firstgid_problem() {
+            return Err(TiledError::InvalidTileset(vec![problem]));
+        }
+        self.tilesets.push(Arc::new(tileset));
+        Ok(self.tilesets.len() - 1)
+    }
+    
+    /// Removes tileset `index` without changing the `firstgid` of any other
+    /// tileset, so their tiles keep their gids. What happens to the tiles
+    /// of the removed tileset still used by tile layers or tile objects
+    /// depends on `policy`, and nothing is changed if it fails.
+    pub fn remove_tileset(&mut self, index: usize, policy: TilesetRemoval) -> Result<Tileset, TiledError> {
+        let name = match self.tilesets.get(index) {
+            Some(set) => set.name.clone(),
+            None => return Err(TiledError::TilesetNotFound(index)),
+        };
+        let used: Vec<LocalTile> = self.all_tile_gids().into_iter()
+            .filter_map(|gid| match gid.find_local(&self.tilesets) {
+                Some((i, tile)) if i == index => Some(tile),
+                _ => None,
+            })
+            .collect();
+        
+        match policy {
+            TilesetRemoval::ErrorIfUsed if !used.is_empty() => return Err(TiledError::TilesetInUse(name)),
+            TilesetRemoval::Remap(ref targets) => {
+                if used.iter().any(|tile| !targets.contains_key(tile)) {
+                    return Err(TiledError::TilesetInUse(name));
+                }
+                // Every target has to be a tile which will still be there
+                for target in targets.values() {
+                    match target.find_local(&self.tilesets) {
+                        Some((i, _)) if i != index => {}
+                        _ => return Err(TiledError::InvalidRemapTarget(target.strip_flags().0)),
+                    }
+                }
+            }
+            _ => {}
+        }
+        
+        if !used.is_empty() {
+            let tilesets = self.tilesets.clone();
+            self.map_gids(|gid| match gid.find_local(&tilesets) {
+                Some((i, tile)) if i == index => match policy {
+                    TilesetRemoval::Remap(ref targets) => match targets.get(&tile) {
+                        Some(&target) => TileFlip::from_gid(gid).apply_to(target),
+                        None => GlobalTile::EMPTY,
+                    },
+                    _ => GlobalTile::EMPTY,
+                },
+                _ => gid,
+            });
+        }
+        
+        let removed = self.tilesets.remove(index);
+        Ok(Arc::try_unwrap(removed).unwrap_or_else(|shared| (*shared).clone()))
+    }
+    
+    /// The first gid after the tiles of every tileset, where a new tileset
+    /// can start
+    fn next_firstgid(&self) -> u32 {
+        self.tilesets.iter()
+            .map(|set| set.firstgid.0.saturating_add(set.tilecount))
+            .max().unwrap_or(1)
+    }
+    
+    /// The tilesets of this level ordered by ascending `firstgid`, regardless
+    /// of the order they were listed in the map file.
+    pub fn tilesets_sorted_by_firstgid(&self) -> Vec<&Tileset> {
+        let mut sets: Vec<&Tileset> = self.tilesets.iter().map(|set| &**set).collect();
+        sets.sort_by_key(|set| set.firstgid.0);
+        sets
+    }
+    
+    /// Checks that `tilesets` is in ascending `firstgid` order as the Tiled
+    /// spec requires. `GlobalTile::find_local` relies on this ordering.
+    pub fn validate_tileset_order(&self) -> bool {
+        self.tilesets.windows(2).all(|pair| pair[0].firstgid.0 < pair[1].firstgid.0)
+    }
+    
+    /// The offset in pixels to draw `layer` at so that it scrolls with its
+    /// parallax factor while the view is centered on the given point.
+    ///
+    /// This matches Tiled's own parallax implementation:
+    /// `offset = (1 - parallax) * (view_center - parallax_origin)`
+    pub fn parallax_offset(&self, layer: &Layer, view_center_x: f32, view_center_y: f32) -> (f32, f32) {
+        self.parallax_shift(layer.parallax(), view_center_x, view_center_y)
+    }
+    
+    /// `parallax_offset` for a layer with the given parallax factor
+    pub(crate) fn parallax_shift(&self, factor: (f32, f32), view_center_x: f32, view_center_y: f32) -> (f32, f32) {
+        let (factor_x, factor_y) = factor;
+        (
+            (1.0 - factor_x) * (view_center_x - self.parallaxoriginx),
+            (1.0 - factor_y) * (view_center_y - self.parallaxoriginy),
+        )
+    }
+    
+    /// Whether the map uses isometric (diamond) projection. All other
+    /// orientations are currently treated as orthogonal.
+    fn is_isometric(&self) -> bool {
+        self.orientation == "isometric"
+    }
+    
+    /// Converts a pixel position to fractional tile coordinates, following
+    /// the projection used by Tiled for the map's orientation
+    fn pixel_to_tile_f(&self, px: f32, py: f32) -> (f32, f32) {
+        let (tile_w, tile_h) = (self.tilewidth as f32, self.tileheight as f32);
+        if self.is_isometric() {
+            // The top corner of tile (0, 0) is in the horizontal middle of the map
+            let px = px - self.height as f32 * tile_w / 2.0;
+            let (x, y) = (px / tile_w, py / tile_h);
+            (y + x, y - x)
+        } else {
+            (px / tile_w, py / tile_h)
+        }
+    }
+    
+    /// The pixel position of a tile's origin: its top-left corner on
+    /// orthogonal maps, or its top corner on isometric maps
+    pub fn tile_to_pixel(&self, x: u32, y: u32) -> (f32, f32) {
+        let (tile_w, tile_h) = (self.tilewidth as f32, self.tileheight as f32);
+        let (x, y) = (x as f32, y as f32);
+        if self.is_isometric() {
+            let origin_x = self.height as f32 * tile_w / 2.0;
+            ((x - y) * tile_w / 2.0 + origin_x, (x + y) * tile_h / 2.0)
+        } else {
+            (x * tile_w, y * tile_h)
+        }
+    }
+    
+    /// The size of the whole map in pixels
+    pub fn pixel_size(&self) -> (u32, u32) {
+        if self.is_isometric() {
+            let diagonal = self.width.saturating_add(self.height);
+            (diagonal.saturating_mul(self.tilewidth) / 2, diagonal.saturating_mul(self.tileheight) / 2)
+        } else {
+            (self.width.saturating_mul(self.tilewidth), self.height.saturating_mul(self.tileheight))
+        }
+    }
+    
+    /// Finds the tile containing the given pixel position, or None if the
+    /// position lies outside of the map.
+    pub fn pixel_to_tile(&self, px: f32, py: f32) -> Option<TileCoord> {
+        let (x, y) = self.pixel_to_tile_f(px, py);
+        let (x, y) = (x.floor(), y.floor());
+        if x >= 0.0 && y >= 0.0 && x < self.width as f32 && y < self.height as f32 {
+            Some(TileCoord { x: x as u32, y: y as u32 })
+        } else {
+            None
+        }
+    }
+    
+    /// Finds the tile containing the given pixel position, clamping positions
+    /// outside of the map to the nearest tile on its edge. Useful for culling
+    /// a camera view which may extend past the edges of the map.
+    pub fn pixel_to_tile_clamped(&self, px: f32, py: f32) -> TileCoord {
+        fn clamp(pos: f32, max: u32) -> u32 {
+            // Written so that NaN also ends up at 0
+            if pos.is_nan() || pos < 0.0 {
+                0
+            } else if pos >= max as f32 {
+                max.saturating_sub(1)
+            } else {
+                pos as u32
+            }
+        }
+        
+        let (x, y) = self.pixel_to_tile_f(px, py);
+        TileCoord {
+            x: clamp(x.floor(), self.width),
+            y: clamp(y.floor(), self.height),
+        }
+    }
+    
+    /// The largest amount any of this level's tilesets overhang a grid cell,
+    /// see `Tileset::overhang`. Never negative.
+    pub fn max_overhang(&self) -> (u32, u32) {
+        self.tilesets.iter().fold((0, 0), |(max_x, max_y), set| {
+            let (x, y) = set.overhang(self.tilewidth, self.tileheight);
+            (max(max_x, max(x, 0) as u32), max(max_y, max(y, 0) as u32))
         })
     }
+    
+    /// The largest tile width and height of any of this level's tilesets,
+    /// which need not come from the same tileset. (0, 0) if there are none.
+    pub fn max_tile_size(&self) -> (u32, u32) {
+        self.tilesets.iter().fold((0, 0), |(max_w, max_h), set| {
+            (max(max_w, set.tilewidth), max(max_h, set.tileheight))
+        })
+    }
+    
+    /// Iterates the non-empty tiles of `layer` which may be visible in the given
+    /// view rectangle (in pixels). The scanned area is expanded by
+    /// `max_overhang` so that oversized tiles whose cell is just outside of
+    /// the view are still included.
+    pub fn visible_tiles<'a>(&self, layer: &'a TileLayer, view_x: f32, view_y: f32, view_w: f32, view_h: f32)
+        -> impl Iterator<Item = (TileCoord, GlobalTile)> + 'a
+    {
+        // Finds the range of cells covering [start, end) along one axis
+        fn span(start: f32, end: f32, size: u32, count: u32) -> (u32, u32) {
+            let clamp = |cell: f32| if cell.is_nan() || cell < 0.0 {
+                0
+            } else if cell >= count as f32 {
+                count
+            } else {
+                cell as u32
+            };
+            let first = (start / size as f32).floor();
+            let last = (end / size as f32).floor() + 1.0;
+            (clamp(first), clamp(last))
+        }
+        
+        // Tiles overhang to the right and upwards, so cells to the left of
+        // and below the view can draw into it
+        let (over_x, over_y) = self.max_overhang();
+        let (min_x, max_x) = span(view_x - over_x as f32, view_x + view_w, self.tilewidth, layer.width);
+        let (min_y, max_y) = span(view_y, view_y + view_h + over_y as f32, self.tileheight, layer.height);
+        
+        (min_y..max_y).flat_map(move |y| (min_x..max_x).map(move |x| TileCoord { x, y }))
+            .filter_map(move |pos| {
+                match layer.tile_at(pos.x, pos.y) {
+                    Some(tile) if tile.0 != 0 => Some((pos, tile)),
+                    _ => None,
+                }
+            })
+    }
+    
+    /// Checks the level for problems which would cause tiles to be resolved
+    /// or rendered incorrectly, returning a description of each one found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        
+        if !self.validate_tileset_order() {
+            problems.push("tilesets are not in ascending firstgid order".to_string());
+        }
+        
+        for set in &self.tilesets {
+            problems.extend(set.firstgid_problem());
+            problems.extend(set.geometry_problems());
+            
+            // Larger tiles are fine, they overlap the cells above them, but
+            // smaller ones leave gaps
+            let (x, y) = set.overhang(self.tilewidth, self.tileheight);
+            if x < 0 || y < 0 {
+                problems.push(format!(
+                    "tileset `{}` has {}x{} tiles, smaller than the level's {}x{} grid",
+                    set.name, set.tilewidth, set.tileheight, self.tilewidth, self.tileheight
+                ));
+            }
+        }
+        
+        for id in self.duplicate_object_ids() {
+            problems.push(format!("more than one object has id {}", id));
+        }
+        
+        for name in self.duplicate_layer_names() {
+            problems.push(format!("more than one layer is named `{}`", name));
+        }
+        
+        problems
+    }
+}
+
+impl Default for Level {
+    fn default() -> Level {
+        Level::empty()
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Level {
+    type Error = TiledError;
+    
+    /// Parses a level from JSON. Image paths are left as written in the JSON,
+    /// and external tilesets can't be used because there is no path to find
+    /// them relative to.
+    fn try_from(json: &'a str) -> Result<Level, TiledError> {
+        Level::load_from_slice(json.as_bytes(), &LoadOptions::default())
+    }
+}
+
+/// What `Level::remove_tileset` does with tiles of the removed tileset which
+/// the level still uses, in tile layers or as the tile of a tile object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TilesetRemoval {
+    /// Fail with `TiledError::TilesetInUse`
+    ErrorIfUsed,
+    /// Clear the cells to empty and turn the tile objects into plain
+    /// rectangles
+    ClearToEmpty,
+    /// Replace each tile with the gid it maps to, a tile of another tileset,
+    /// keeping how it's flipped. Fails with `TilesetInUse` if a tile which is
+    /// used has no replacement, or `InvalidRemapTarget` if a gid it maps to
+    /// isn't a tile of another tileset.
+    Remap(HashMap<LocalTile, GlobalTile>),
+}
+
+/// The outcome of loading several levels with `Level::load_many` or
+/// `Level::load_dir`
+#[derive(Debug)]
+pub struct BatchResult {
+    /// The levels which loaded, sorted by path
+    pub loaded: Vec<(PathBuf, Level)>,
+    /// The levels which failed to load and why, sorted by path
+    pub failed: Vec<(PathBuf, JsonError)>,
+}
+
+/// What a custom property found by `Level::iter_all_properties` belongs to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PropertyOwner<'a> {
+    /// The level itself
+    Map,
+    /// The layer with the given name
+    Layer(&'a str),
+    /// The object with the given id, in the named object layer
+    Object { layer: &'a str, id: u32 },
+    /// The tileset with the given name
+    Tileset(&'a str),
+    /// A single tile in the named tileset
+    Tile { tileset: &'a str, tile: LocalTile },
+}
+
+impl<'a> fmt::Display for PropertyOwner<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PropertyOwner::Map => f.write_str("the map"),
+            PropertyOwner::Layer(name) => write!(f, "layer `{}`", name),
+            PropertyOwner::Object { layer, id } => write!(f, "object {} in layer `{}`", id, layer),
+            PropertyOwner::Tileset(name) => write!(f, "tileset `{}`", name),
+            PropertyOwner::Tile { tileset, tile } => write!(f, "tile {} of tileset `{}`", tile.0, tileset),
+        }
+    }
+}
+
+/// A custom property along with what it belongs to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PropertyRef<'a> {
+    pub owner: PropertyOwner<'a>,
+    pub key: &'a str,
+    pub value: &'a str,
+}
+
+/// The tileset whose range `id` falls in, which is the one with the highest
+/// firstgid that is still <= the id
+fn range_owner(id: u32, tilesets: &[Arc<Tileset>]) -> Option<&Arc<Tileset>> {
+    tilesets.iter()
+        .filter(|set| set.firstgid.0 <= id)
+        .max_by_key(|set| set.firstgid.0)
+}
+
+/// Describes why `gid` doesn't refer to a valid tile, if it doesn't. Gid 0
+/// is always valid as it represents the absence of a tile.
+fn gid_problem(gid: GlobalTile, tilesets: &[Arc<Tileset>]) -> Option<String> {
+    let id = gid.strip_flags().0;
+    if id == 0 {
+        return None;
+    }
+    
+    match range_owner(id, tilesets) {
+        None => Some(format!("gid {} is not in the range of any tileset", id)),
+        Some(set) if id - set.firstgid.0 >= set.tilecount => Some(format!(
+            "gid {} is past the end of tileset `{}` ({} tiles)", id, set.name, set.tilecount
+        )),
+        Some(_) => None,
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -56,7 +1878,7 @@ struct IntermediateLevel {
     height: u32,
     width: u32,
     
-    properties: HashMap<String, String>,
+    properties: Properties,
     
     orientation: String,
     renderorder: String,
@@ -64,13 +1886,635 @@ struct IntermediateLevel {
     tileheight: u32,
     tilewidth: u32,
     
-    layers: Vec<Layer>,
+    parallaxoriginx: Option<f32>,
+    parallaxoriginy: Option<f32>,
+    
+    nextobjectid: Option<u32>,
+    
+    layers: Vec<JsonValue>,
     tilesets: Vec<JsonValue>,
 }
 
+/// The JSON of a `width` by `height` level with 16x16 tiles, for tests.
+/// `fields` are any other top-level fields, each followed by a comma, with
+/// empty properties unless it has its own. `layers` and `tilesets` are the
+/// contents of those arrays.
+#[cfg(test)]
+fn level_json(width: u32, height: u32, fields: &str, layers: &str, tilesets: &str) -> String {
+    let properties = if fields.contains("\"properties\"") { "" } else { r#""properties": {},"# };
+    format!(r#"{{
+        "height": {}, "width": {}, "tileheight": 16, "tilewidth": 16,
+        "orientation": "orthogonal", "renderorder": "right-down", {}
+        {}
+        "layers": [{}],
+        "tilesets": [{}]
+    }}"#, height, width, properties, fields, layers, tilesets)
+}
+
+/// The JSON of a visible tile layer, for tests. `data` is the array's contents.
+#[cfg(test)]
+fn tile_layer_json(name: &str, width: u32, height: u32, data: &str) -> String {
+    format!(r#"{{
+        "type": "tilelayer", "name": "{}", "opacity": 1, "visible": true,
+        "width": {}, "height": {}, "x": 0, "y": 0, "data": [{}]
+    }}"#, name, width, height, data)
+}
+
+/// The JSON of a visible object layer, for tests. `objects` is the array's
+/// contents.
+#[cfg(test)]
+fn object_layer_json(name: &str, objects: &str) -> String {
+    format!(r#"{{
+        "type": "objectgroup", "name": "{}", "opacity": 1, "visible": true,
+        "width": 0, "height": 0, "x": 0, "y": 0, "draworder": "topdown",
+        "objects": [{}]
+    }}"#, name, objects)
+}
+
+/// The JSON of a door object for tests, with an object-typed `target`
+/// property. A `gid` of 0 means the object has no tile.
+#[cfg(test)]
+fn door_json(id: u32, target: u32, gid: u32) -> String {
+    format!(r#"{{
+        "id": {}, "name": "", "type": "door", "rotation": 0, "visible": true,
+        "width": 16, "height": 16, "x": 0, "y": 0, "gid": {},
+        "properties": [{{ "name": "target", "type": "object", "value": {} }}]
+    }}"#, id, gid, target)
+}
+
+/// The JSON of a tileset of four 16x16 tiles in two columns, for tests.
+#[cfg(test)]
+fn tileset_json(name: &str, firstgid: u32) -> String {
+    format!(r#"{{
+        "name": "{}", "firstgid": {}, "tilecount": 4, "columns": 2,
+        "tilewidth": 16, "tileheight": 16, "image": "tiles.png",
+        "imagewidth": 32, "imageheight": 32, "margin": 0, "spacing": 0,
+        "tileproperties": {{}}, "tiles": {{}}
+    }}"#, name, firstgid)
+}
+
 #[test]
 pub fn load_level() {
     let path = "test-assets/levels/simple2.json";
     let _ = Level::load(path).unwrap();
 }
 
+#[test]
+pub fn load_dir_reports_every_level() {
+    let result = Level::load_dir("test-assets/levels", &LoadOptions::default()).unwrap();
+    assert_eq!(result.loaded.len(), 1);
+    assert!(result.failed.is_empty());
+    
+    let result = Level::load_many(["test-assets/missing.json", "test-assets/levels/simple2.json"], &LoadOptions::default());
+    assert_eq!(result.loaded[0].0, PathBuf::from("test-assets/levels/simple2.json"));
+    assert_eq!(result.failed[0].0, PathBuf::from("test-assets/missing.json"));
+}
+
+#[test]
+pub fn parallax_offset_with_origin() {
+    use layer::Layer;
+    
+    let path = "test-assets/levels/simple2.json";
+    let mut level = Level::load(path).unwrap();
+    level.parallaxoriginx = 64.0;
+    level.parallaxoriginy = 32.0;
+    if let Layer::Tiles(ref mut layer) = level.layers[0] {
+        layer.parallaxx = Some(0.5);
+        layer.parallaxy = Some(0.5);
+    }
+    
+    let offset = level.parallax_offset(&level.layers[0], 320.0, 240.0);
+    assert_eq!(offset, (128.0, 104.0));
+    
+    // Layers without a parallax factor scroll with the camera
+    let offset = level.parallax_offset(&level.layers[1], 320.0, 240.0);
+    assert_eq!(offset, (0.0, 0.0));
+}
+
+/// Every field name here is exactly as Tiled writes it, so this catches any
+/// struct field which doesn't match its JSON name
+#[test]
+pub fn deserialize_tiled_field_names() {
+    use layer::Layer;
+    
+    let json = r##"{
+        "height": 2, "width": 2, "tileheight": 16, "tilewidth": 16,
+        "orientation": "orthogonal", "renderorder": "right-down",
+        "nextobjectid": 2, "parallaxoriginx": 4, "parallaxoriginy": 8,
+        "properties": { "music": "theme.ogg" },
+        "layers": [
+            {
+                "type": "tilelayer", "name": "ground", "id": 1,
+                "opacity": 1, "visible": true, "width": 2, "height": 2, "x": 0, "y": 0,
+                "parallaxx": 0.5, "parallaxy": 0.5,
+                "properties": { "collides": "true" },
+                "data": [1, 2, 0, 1]
+            },
+            {
+                "type": "objectgroup", "name": "objects", "id": 2, "color": "#ff0000",
+                "opacity": 1, "visible": true, "width": 2, "height": 2, "x": 0, "y": 0,
+                "draworder": "topdown",
+                "objects": [{
+                    "id": 1, "name": "door", "type": "trigger", "gid": 2,
+                    "polygon": [{ "x": 0, "y": 0 }, { "x": 16, "y": 0 }, { "x": 0, "y": 16 }],
+                    "properties": { "target": "hall" },
+                    "rotation": 0, "visible": true, "width": 16, "height": 16, "x": 8, "y": 24
+                }]
+            }
+        ],
+        "tilesets": [{
+            "name": "tiles", "firstgid": 1, "tilecount": 4, "columns": 2,
+            "tilewidth": 16, "tileheight": 16, "image": "tiles.png",
+            "imagewidth": 32, "imageheight": 32, "margin": 0, "spacing": 0,
+            "properties": { "kind": "terrain" },
+            "terrains": [{ "name": "grass", "tile": 0 }],
+            "tileproperties": { "1": { "solid": "true" } },
+            "tiles": { "0": { "terrain": [0, 0, 0, 0] } }
+        }]
+    }"##;
+    
+    let level = Level::try_from(json).unwrap();
+    assert_eq!(level.nextobjectid, 2);
+    assert_eq!(level.parallaxoriginy, 8.0);
+    assert_eq!(level.properties.get_str("music"), Some("theme.ogg"));
+    
+    match level.layers[0] {
+        Layer::Tiles(ref tiles) => {
+            assert_eq!(tiles.id, Some(1));
+            assert_eq!(tiles.parallaxx, Some(0.5));
+            assert_eq!(tiles.data, vec![GlobalTile(1), GlobalTile(2), GlobalTile(0), GlobalTile(1)]);
+        }
+        _ => panic!("expected a tile layer"),
+    }
+    match level.layers[1] {
+        Layer::Objects(ref objects) => {
+            assert_eq!(objects.draworder, "topdown");
+            assert!(objects.color.is_some());
+            let object = &objects.objects[0];
+            assert_eq!(object.kind, "trigger");
+            assert_eq!(object.gid, Some(GlobalTile(2)));
+            assert_eq!(object.polygon.as_ref().map(|p| p.len()), Some(3));
+            assert_eq!(object.properties.get_str("target"), Some("hall"));
+        }
+        _ => panic!("expected an object layer"),
+    }
+    
+    let set = &level.tilesets[0];
+    assert_eq!((set.imagewidth, set.imageheight), (32, 32));
+    assert_eq!(set.terrains.as_ref().unwrap()[0].tile, LocalTile(0));
+    assert_eq!(set.tileproperties.tiles[&LocalTile(1)].get_bool("solid"), Some(true));
+    assert_eq!(set.tiles.tiles[&LocalTile(0)], [0, 0, 0, 0]);
+}
+
+#[test]
+pub fn object_ids_and_references() {
+    let objects = format!("{}, {}, {}", door_json(5, 7, 0), door_json(7, 5, 0), door_json(5, 7, 0));
+    let json = level_json(1, 1, r#""nextobjectid": 8,"#, &object_layer_json("doors", &objects), "");
+    
+    let mut level = Level::try_from(&json[..]).unwrap();
+    assert_eq!(level.duplicate_object_ids(), vec![5]);
+    assert_eq!(level.warnings.len(), 1);
+    
+    let mut crowded = level.clone();
+    crowded.nextobjectid = u32::MAX;
+    assert!(crowded.reassign_object_ids().is_err());
+    assert_eq!(crowded.duplicate_object_ids(), vec![5]);
+    
+    assert_eq!(level.reassign_object_ids().unwrap(), 1);
+    assert!(level.duplicate_object_ids().is_empty());
+    assert_eq!(level.nextobjectid, 9);
+    
+    assert!(level.renumber_objects(u32::MAX - 2).is_err());
+    level.renumber_objects(1).unwrap();
+    let objects = &level.object_layers().next().unwrap().objects;
+    let ids: Vec<u32> = objects.iter().map(|o| o.id).collect();
+    let targets: Vec<&str> = objects.iter().map(|o| o.properties.get_str("target").unwrap()).collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(targets, vec!["2", "1", "2"]);
+}
+
+#[test]
+fn hostile_input_is_an_error() {
+    let options = LoadOptions { lenient_layer_data: true, ..LoadOptions::default() };
+    
+    assert!(Level::load_from_slice(b"{\"width\": \xff", &options).is_err());
+    let nested = vec![b'['; 100000];
+    assert!(Level::load_from_slice(&nested, &options).is_err());
+    
+    let huge = level_json(100000, 100000, "", &tile_layer_json("ground", 100000, 100000, "0"), "");
+    assert!(Level::load_from_slice(huge.as_bytes(), &options).is_err());
+}
+
+#[test]
+fn uniform_properties_on_every_owner() {
+    let layers = r#"
+        {
+            "type": "tilelayer", "name": "ground", "properties": WEIGHT,
+            "opacity": 1, "visible": true, "width": 1, "height": 1, "x": 0, "y": 0, "data": [1]
+        },
+        {
+            "type": "objectgroup", "name": "objects", "properties": WEIGHT,
+            "opacity": 1, "visible": true, "width": 1, "height": 1, "x": 0, "y": 0,
+            "draworder": "topdown",
+            "objects": [{
+                "id": 1, "name": "", "type": "", "properties": WEIGHT,
+                "rotation": 0, "visible": true, "width": 0, "height": 0, "x": 0, "y": 0
+            }]
+        }
+    "#;
+    let tileset = r#"{
+        "name": "tiles", "firstgid": 1, "tilecount": 1, "columns": 1,
+        "tilewidth": 16, "tileheight": 16, "image": "tiles.png",
+        "imagewidth": 16, "imageheight": 16, "margin": 0, "spacing": 0,
+        "properties": WEIGHT,
+        "tileproperties": { "0": WEIGHT },
+        "tiles": {}
+    }"#;
+    let json = level_json(1, 1, r#""properties": WEIGHT,"#, layers, tileset)
+        .replace("WEIGHT", r#"[{ "name": "weight", "type": "int", "value": 3 }]"#);
+    let level = Level::try_from(&json[..]).unwrap();
+    
+    let set = &level.tilesets[0];
+    let mut owners = vec![&level.properties, &set.properties, &set.tileproperties.tiles[&LocalTile(0)]];
+    owners.extend(level.layers.iter().map(|layer| layer.properties()));
+    owners.extend(level.object_layers().flat_map(|layer| &layer.objects).map(|object| &object.properties));
+    assert_eq!(owners.len(), 6);
+    for properties in owners {
+        assert_eq!(properties.get_type("weight"), Some("int"));
+        assert_eq!(properties.get_i64("weight"), Some(3));
+    }
+    
+    let chain = level.property_chain_for_object(1).unwrap();
+    assert_eq!(chain.get_type("weight"), Some("int"));
+}
+
+#[test]
+fn tileset_coverage() {
+    // The second cell is tile 2 flipped horizontally
+    let layer = tile_layer_json("ground", 2, 2, "1, 2147483650, 2, 0");
+    let tilesets = format!("{}, {}", tileset_json("grass", 1), tileset_json("water", 5));
+    let json = level_json(2, 2, "", &layer, &tilesets);
+    
+    let level = Level::try_from(&json[..]).unwrap();
+    assert_eq!(level.all_tile_gids(), [GlobalTile(1), GlobalTile(2)].iter().cloned().collect());
+    assert_eq!(level.compute_tileset_coverage(), vec![
+        ("grass".to_string(), 2, 50.0),
+        ("water".to_string(), 0, 0.0),
+    ]);
+}
+
+#[test]
+fn layer_order_contract() {
+    let mut level = Level::empty();
+    level.layers.push(TileLayer::generate_from("ground", 1, 1, |_, _| GlobalTile::EMPTY).to_layer());
+    level.layers.push(TileLayer::generate_from("walls", 1, 1, |_, _| GlobalTile::EMPTY).to_layer());
+    
+    assert!(level.assert_layer_order(&["ground", "walls"]).is_ok());
+    let error = level.assert_layer_order(&["walls", "ground"]).unwrap_err();
+    assert_eq!(error.to_string(), "Layers are not in the expected order, expected [walls, ground] but found [ground, walls]");
+    assert!(level.assert_layer_order(&["ground"]).is_err());
+}
+
+#[test]
+fn partial_load_of_unsupported_parts() {
+    let layers = format!(r#"{}, {{ "type": "group", "name": "decor", "id": 4, "layers": [] }}, {{
+        "type": "tilelayer", "name": "packed", "opacity": 1, "visible": true,
+        "width": 1, "height": 1, "x": 0, "y": 0, "encoding": "base64", "data": "AQAAAA=="
+    }}"#, tile_layer_json("ground", 1, 1, "1"));
+    let json = level_json(1, 1, "", &layers, r#"{ "firstgid": 1, "source": "tiles.tsx" }"#);
+    
+    assert!(Level::load_from_slice(json.as_bytes(), &LoadOptions::default()).is_err());
+    
+    let options = LoadOptions { allow_partial: true, ..LoadOptions::default() };
+    let level = Level::load_from_slice(json.as_bytes(), &options).unwrap();
+    assert_eq!(level.layer_names(), vec!["ground", "decor", "packed"]);
+    assert_eq!(level.layers[1].id(), Some(4));
+    assert_eq!(level.tilesets[0].firstgid, GlobalTile(1));
+    assert_eq!(level.warnings.len(), 3);
+    assert!(level.warnings[0].starts_with("Layer `decor` (/layers/1) was replaced with an empty tile layer: "));
+    assert!(level.warnings[2].starts_with("Tileset `tiles.tsx` (/tilesets/0) was replaced with a placeholder: "));
+    // The placeholder's tiles aren't known, so its gids are kept as they are
+    assert_eq!(level.tile_layers().next().unwrap().data, vec![GlobalTile(1)]);
+    
+    let options = LoadOptions { gid_integrity: GidIntegrity::Error, ..options };
+    assert!(Level::load_from_slice(json.as_bytes(), &options).is_ok());
+}
+
+#[test]
+fn patch_by_source_pointer() {
+    let object = r#"{ "id": 1, "name": "", "type": "", "properties": {}, "rotation": 0,
+                      "visible": true, "width": 0, "height": 0, "x": 0, "y": 0 }"#;
+    let layers = format!("{}, {}", tile_layer_json("ground", 1, 1, "0"), object_layer_json("things", object));
+    let json = level_json(1, 1, "", &layers, "");
+    
+    let mut level = Level::try_from(&json[..]).unwrap();
+    assert!(level.apply_json_patch("/layers/1/objects/0/x", JsonValue::from(5)).is_err());
+    
+    let options = LoadOptions { record_source_pointers: true, ..LoadOptions::default() };
+    let mut level = Level::load_from_slice(json.as_bytes(), &options).unwrap();
+    level.layers.insert(0, TileLayer::generate_from("sky", 1, 1, |_, _| GlobalTile::EMPTY).to_layer());
+    assert_eq!(level.layers[2].source_pointer(), Some("/layers/1".to_string()));
+    
+    level.apply_json_patch("/layers/1/objects/0/x", JsonValue::from(12.5)).unwrap();
+    level.apply_json_patch("/layers/1/objects/0/properties/on~1off", JsonValue::Bool(true)).unwrap();
+    level.apply_json_patch("/layers/0/visible", JsonValue::Bool(false)).unwrap();
+    let object = &level.object_layers().next().unwrap().objects[0];
+    assert_eq!(object.source_pointer(), Some("/layers/1/objects/0".to_string()));
+    assert_eq!((object.x, object.properties.get_bool("on/off")), (12.5, Some(true)));
+    assert!(!level.layers[1].visible());
+    
+    let error = level.apply_json_patch("/layers/0/visible", JsonValue::from(1)).unwrap_err();
+    assert!(error.to_string().starts_with("Can't apply JSON patch at `/layers/0/visible`: the value has the wrong type"));
+    assert!(level.apply_json_patch("/layers/0/data/0", JsonValue::from(1)).is_err());
+}
+
+#[test]
+fn add_and_remove_tilesets() {
+    let tileset = |name: &str| Tileset::placeholder(name)
+        .with_tilesize(16, 16).with_columns(2).with_tilecount(4).with_image_size(32, 32)
+        .build().unwrap();
+    let flipped = TileFlip { horizontal: true, vertical: false, diagonal: false };
+    
+    let mut level = Level::empty();
+    level.tilewidth = 16;
+    level.tileheight = 16;
+    assert_eq!(level.add_tileset(tileset("grass")).unwrap(), 0);
+    assert_eq!(level.add_tileset(tileset("water")).unwrap(), 1);
+    assert_eq!(level.tilesets[1].firstgid, GlobalTile(5));
+    
+    let data = vec![GlobalTile(1), flipped.apply_to(GlobalTile(6)), GlobalTile(5)];
+    level.layers.push(TileLayer { width: 3, height: 1, data, ..TileLayer::EMPTY }.to_layer());
+    assert!(level.validate().is_empty());
+    
+    let error = level.remove_tileset(1, TilesetRemoval::ErrorIfUsed).unwrap_err();
+    assert_eq!(error.to_string(), "Tiles are still used from tileset `water`");
+    assert!(level.remove_tileset(1, TilesetRemoval::Remap(HashMap::new())).is_err());
+    
+    // Targets must be tiles of the tilesets which are staying
+    let missing = vec![(LocalTile(0), GlobalTile(2)), (LocalTile(1), GlobalTile(99))].into_iter().collect();
+    let error = level.remove_tileset(1, TilesetRemoval::Remap(missing)).unwrap_err();
+    assert_eq!(error.to_string(), "Tiles can't be remapped to gid 99");
+    let removed = vec![(LocalTile(0), GlobalTile(2)), (LocalTile(1), GlobalTile(6))].into_iter().collect();
+    assert!(level.remove_tileset(1, TilesetRemoval::Remap(removed)).is_err());
+    assert_eq!(level.tilesets.len(), 2);
+    assert!(level.validate().is_empty());
+    
+    let targets = vec![(LocalTile(0), GlobalTile(2)), (LocalTile(1), GlobalTile(3))].into_iter().collect();
+    let mut remapped = level.clone();
+    assert_eq!(remapped.remove_tileset(1, TilesetRemoval::Remap(targets)).unwrap().name, "water");
+    assert_eq!(remapped.tile_layers().next().unwrap().data, vec![GlobalTile(1), flipped.apply_to(GlobalTile(3)), GlobalTile(2)]);
+    assert!(remapped.validate().is_empty());
+    
+    level.remove_tileset(0, TilesetRemoval::ClearToEmpty).unwrap();
+    assert_eq!(level.tilesets[0].firstgid, GlobalTile(5));
+    assert_eq!(level.tile_layers().next().unwrap().data, vec![GlobalTile::EMPTY, flipped.apply_to(GlobalTile(6)), GlobalTile(5)]);
+    assert!(level.validate().is_empty());
+    
+    // The last tile of `last` has the largest gid there can be
+    let mut last = tileset("last");
+    last.firstgid = GlobalTile(!FLIP_FLAGS - 3);
+    level.push_tileset(last);
+    assert!(level.add_tileset(tileset("more")).is_err());
+    assert_eq!(level.tilesets.len(), 2);
+}
+
+#[test]
+fn compress_only_identical_tilesets() {
+    let tileset = |columns: u32, tilecount: u32| Tileset::placeholder("grass")
+        .with_tilesize(16, 16).with_columns(columns).with_tilecount(tilecount).with_image_size(32, 32)
+        .build().unwrap();
+    
+    let mut level = Level::empty();
+    level.add_tileset(tileset(2, 4)).unwrap();
+    level.add_tileset(tileset(2, 4)).unwrap();
+    level.add_tileset(tileset(2, 2)).unwrap();
+    level.add_tileset(tileset(1, 2)).unwrap();
+    let mut solid = tileset(2, 4);
+    solid.tileproperties.tiles.insert(LocalTile(0), Properties::new());
+    level.add_tileset(solid).unwrap();
+    let data = vec![GlobalTile(6), GlobalTile(9), GlobalTile(11), GlobalTile(13)];
+    level.layers.push(TileLayer { width: 4, height: 1, data, ..TileLayer::EMPTY }.to_layer());
+    
+    assert_eq!(level.compress_tilesets(), 1);
+    let firstgids: Vec<u32> = level.tilesets.iter().map(|set| set.firstgid.0).collect();
+    assert_eq!(firstgids, vec![1, 9, 11, 13]);
+    assert_eq!(level.tile_layers().next().unwrap().data, vec![GlobalTile(2), GlobalTile(9), GlobalTile(11), GlobalTile(13)]);
+    assert!(level.validate().is_empty());
+}
+
+#[test]
+fn reload_keeps_source_pointers() {
+    let options = LoadOptions { record_source_pointers: true, ..LoadOptions::default() };
+    let mut level = Level::load_with_options("test-assets/levels/simple2.json", &options).unwrap();
+    
+    assert!(!level.reload_tilesets().unwrap());
+    assert_eq!(level.tilesets[0].source_pointer(), Some("/tilesets/0".to_string()));
+}
+
+#[test]
+fn unchanged_tilesets_stay_shared() {
+    let mut level = Level::load("test-assets/levels/simple2.json").unwrap();
+    let copy = level.clone();
+    
+    assert!(!level.reload_tilesets().unwrap());
+    level.map_properties(|_, _, _| {});
+    assert!(Arc::ptr_eq(&level.tilesets[0], &copy.tilesets[0]));
+    
+    level.map_properties(|_, _, value| value.push('!'));
+    assert!(!Arc::ptr_eq(&level.tilesets[0], &copy.tilesets[0]));
+}
+
+#[test]
+fn overlay_copies_objects_with_their_links() {
+    fn level(next_id: u32, objects: &str) -> Level {
+        let fields = format!(r#""nextobjectid": {},"#, next_id);
+        Level::try_from(&level_json(1, 1, &fields, &object_layer_json("doors", objects), "")[..]).unwrap()
+    }
+    
+    let mut base = level(4, &door_json(3, 3, 0));
+    let mut patch = level(5, &format!("{}, {}", door_json(3, 4, 0), door_json(4, 3, 99)));
+    if let Layer::Objects(ref mut layer) = patch.layers[0] {
+        layer.properties = serde_json::from_str(r#"[{ "name": "exit", "type": "object", "value": 4 }]"#).unwrap();
+    }
+    
+    let mut crowded = base.clone();
+    crowded.nextobjectid = u32::MAX - 1;
+    assert!(crowded.overlay(&patch, TileCoord { x: 0, y: 0 }).is_err());
+    assert_eq!(crowded.object_layers().next().unwrap().objects.len(), 1);
+    
+    base.overlay(&patch, TileCoord { x: 0, y: 0 }).unwrap();
+    let objects = &base.object_layers().next().unwrap().objects;
+    let ids: Vec<u32> = objects.iter().map(|o| o.id).collect();
+    let targets: Vec<&str> = objects.iter().map(|o| o.properties.get_str("target").unwrap()).collect();
+    assert_eq!(ids, vec![3, 4, 5]);
+    assert_eq!(targets, vec!["3", "5", "4"]);
+    assert_eq!(objects[2].gid, None);
+    assert_eq!(base.nextobjectid, 6);
+    let properties = base.layers[0].properties();
+    assert_eq!((properties.get_str("exit"), properties.get_type("exit")), (Some("5"), Some("object")));
+    assert_eq!(base.warnings, vec!["Layer `doors` object 5: gid 99 isn't in any of the patch's tilesets, so it was left out"]);
+}
+
+#[test]
+fn overlay_matches_tilesets_by_grid() {
+    fn level(tilecount: u32, data: Vec<GlobalTile>) -> Level {
+        let mut level = Level::empty();
+        level.width = 2;
+        level.height = 1;
+        level.add_tileset(Tileset::placeholder("grass")
+            .with_tilesize(16, 16).with_columns(2).with_tilecount(tilecount).with_image_size(32, 32)
+            .build().unwrap()).unwrap();
+        level.layers.push(TileLayer { name: "ground".to_string(), width: 2, height: 1, data, ..TileLayer::EMPTY }.to_layer());
+        level
+    }
+    
+    let mut base = level(4, vec![GlobalTile(3), GlobalTile(4)]);
+    base.overlay(&level(2, vec![GlobalTile(2), GlobalTile(7)]), TileCoord { x: 0, y: 0 }).unwrap();
+    let firstgids: Vec<u32> = base.tilesets.iter().map(|set| set.firstgid.0).collect();
+    assert_eq!(firstgids, vec![1, 5]);
+    assert_eq!(base.tile_layers().next().unwrap().data, vec![GlobalTile(6), GlobalTile(4)]);
+    assert_eq!(base.warnings, vec!["Layer `ground` tile (1, 0): gid 7 isn't in any of the patch's tilesets, so it was left out"]);
+    
+    base.overlay(&level(4, vec![GlobalTile::EMPTY, GlobalTile(2)]), TileCoord { x: 0, y: 0 }).unwrap();
+    assert_eq!(base.tilesets.len(), 2);
+    assert_eq!(base.tile_layers().next().unwrap().data, vec![GlobalTile(6), GlobalTile(2)]);
+}
+
+#[test]
+fn gid_integrity_modes() {
+    // Gid 3 is before every tileset and gid 9 is past the end of `grass`
+    let tilesets = format!("{}, {}", tileset_json("grass", 5), tileset_json("water", 11));
+    let json = level_json(2, 2, "", &tile_layer_json("ground", 2, 2, "5, 3, 9, 11"), &tilesets);
+    
+    let options = LoadOptions { gid_integrity: GidIntegrity::Clear, ..LoadOptions::default() };
+    let level = Level::load_from_slice(json.as_bytes(), &options).unwrap();
+    assert_eq!(level.tile_layers().next().unwrap().data, vec![GlobalTile(5), GlobalTile::EMPTY, GlobalTile::EMPTY, GlobalTile(11)]);
+    assert_eq!(level.warnings, vec![
+        "Layer `ground` tile 1: gid 3 is not in the range of any tileset",
+        "Layer `ground` tile 2: gid 9 is past the end of tileset `grass` (4 tiles)",
+    ]);
+    
+    let options = LoadOptions { gid_integrity: GidIntegrity::Error, ..LoadOptions::default() };
+    let error = Level::load_from_slice(json.as_bytes(), &options).unwrap_err();
+    assert!(error.to_string().contains("Layer `ground` tile 1: gid 3 is not in the range of any tileset"));
+}
+
+#[test]
+fn load_errors_point_at_the_field() {
+    let object = r#"{
+            "id": 1, "name": "", "type": "", "visible": true,
+            "width": 0, "height": 0, "x": 0, "y": 0, "rotation": "left"
+        }"#;
+    let json = level_json(1, 1, "", &object_layer_json("things", object), "");
+    
+    let error = Level::try_from(&json[..]).unwrap_err();
+    match error {
+        TiledError::InvalidValue { ref pointer, line, column, .. } => {
+            assert_eq!(pointer, "/layers/0/objects/0/rotation");
+            assert_eq!((line, column), (10, 66));
+        }
+        _ => panic!("expected an invalid value, found {}", error),
+    }
+    assert_eq!(
+        error.to_string(),
+        r#"Invalid value at `/layers/0/objects/0/rotation` (line 10 column 66): invalid type: string "left", expected f32"#
+    );
+    
+    let tileset = tileset_json("grass", 1).replace(r#""columns": 2"#, r#""columns": "two""#);
+    let error = Level::try_from(&level_json(1, 1, "", "", &tileset)[..]).unwrap_err();
+    assert_eq!(error.to_string(), r#"Invalid value at `/tilesets/0/columns` (line 7 column 68): invalid type: string "two", expected u32"#);
+}
+
+#[test]
+fn map_properties_keeps_declared_types() {
+    use properties::PropertyValue;
+    
+    let properties = r#""properties": [
+        { "name": "speed", "type": "float", "value": 2.5 },
+        { "name": "title", "type": "string", "value": "Cave" }
+    ],"#;
+    let mut level = Level::try_from(&level_json(1, 1, properties, "", "")[..]).unwrap();
+    
+    level.map_properties(|_, _, value| *value = "fast".to_string());
+    assert_eq!(level.properties.get_f64("speed"), Some(2.5));
+    assert_eq!(level.properties.get_str("title"), Some("fast"));
+    assert_eq!(level.warnings, vec!["Property `speed` of the map can't be `fast` as it is declared as float, keeping `2.5`"]);
+    
+    level.map_properties(|_, key, value| if key == "speed" { *value = "3.5".to_string() });
+    assert_eq!(level.properties.get_value("speed"), Some(PropertyValue::Float(3.5)));
+    assert_eq!(level.warnings.len(), 1);
+}
+
+#[test]
+fn mirror_tiles_and_objects() {
+    use layer::PolyPoint;
+    
+    let objects = r#"
+        { "id": 1, "name": "", "type": "", "properties": {}, "rotation": 0, "visible": true,
+          "width": 16, "height": 8, "x": 8, "y": 4 },
+        { "id": 2, "name": "", "type": "", "properties": {}, "rotation": 0, "visible": true,
+          "width": 16, "height": 16, "x": 0, "y": 16, "gid": 1 },
+        { "id": 3, "name": "", "type": "", "properties": {}, "rotation": 90, "visible": true,
+          "width": 0, "height": 0, "x": 16, "y": 16,
+          "polygon": [{ "x": 0, "y": 0 }, { "x": 8, "y": 0 }, { "x": 0, "y": 8 }] }
+    "#;
+    let layers = format!("{}, {}", tile_layer_json("ground", 4, 2, "1, 2, 0, 0, 0, 0, 0, 3"), object_layer_json("things", objects));
+    let level = Level::try_from(&level_json(4, 2, "", &layers, &tileset_json("tiles", 1))[..]).unwrap();
+    let horizontal = TileFlip { horizontal: true, vertical: false, diagonal: false };
+    let vertical = TileFlip { horizontal: false, vertical: true, diagonal: false };
+    let points = |object: &Object| -> Vec<(f32, f32)> {
+        object.polygon.as_ref().unwrap().iter().map(|&PolyPoint { x, y }| (x, y)).collect()
+    };
+    let tiles = |ids: &[u32], flip: TileFlip| -> Vec<GlobalTile> {
+        ids.iter().map(|&id| if id == 0 { GlobalTile::EMPTY } else { flip.apply_to(GlobalTile(id)) }).collect()
+    };
+    
+    let mut mirrored = level.clone();
+    mirrored.mirror_horizontal();
+    assert_eq!(mirrored.tile_layers().next().unwrap().data, tiles(&[0, 0, 2, 1, 3, 0, 0, 0], horizontal));
+    let objects = &mirrored.object_layers().next().unwrap().objects;
+    assert_eq!((objects[0].x, objects[0].y), (40.0, 4.0));
+    assert_eq!((objects[1].x, objects[1].y, objects[1].gid), (48.0, 16.0, Some(horizontal.apply_to(GlobalTile(1)))));
+    assert_eq!((objects[2].x, objects[2].y, objects[2].rotation), (48.0, 16.0, -90.0));
+    assert_eq!(points(&objects[2]), vec![(0.0, 0.0), (-8.0, 0.0), (0.0, 8.0)]);
+    
+    mirrored.mirror_horizontal();
+    assert_eq!(mirrored.tile_layers().next().unwrap().data, level.tile_layers().next().unwrap().data);
+    
+    let mut mirrored = level.clone();
+    mirrored.mirror_vertical();
+    assert_eq!(mirrored.tile_layers().next().unwrap().data, tiles(&[0, 0, 0, 3, 1, 2, 0, 0], vertical));
+    let objects = &mirrored.object_layers().next().unwrap().objects;
+    assert_eq!((objects[0].x, objects[0].y), (8.0, 20.0));
+    assert_eq!((objects[1].x, objects[1].y, objects[1].gid), (0.0, 32.0, Some(vertical.apply_to(GlobalTile(1)))));
+    assert_eq!((objects[2].x, objects[2].y, objects[2].rotation), (16.0, 16.0, -90.0));
+    assert_eq!(points(&objects[2]), vec![(0.0, 0.0), (8.0, 0.0), (0.0, -8.0)]);
+}
+
+#[test]
+fn overlay_tile_layers_keeps_the_topmost_tile() {
+    let mut level = Level::empty();
+    level.width = 2;
+    level.height = 2;
+    assert_eq!(level.overlay_tile_layers().unwrap_err().to_string(), "The level has no tile layers");
+    
+    let layer = |name: &str, ids: &[u32]| TileLayer {
+        name: name.to_string(),
+        width: 2,
+        height: 2,
+        data: ids.iter().map(|&id| GlobalTile(id)).collect(),
+        ..TileLayer::EMPTY
+    }.to_layer();
+    level.layers.push(layer("ground", &[1, 1, 1, 0]));
+    level.layers.push(serde_json::from_str::<ObjectLayer>(&object_layer_json("things", "")).unwrap().to_layer());
+    level.layers.push(layer("walls", &[0, 2, 0, 0]));
+    level.layers.push(layer("decor", &[0, 3, 3, 0]));
+    
+    let flat = level.overlay_tile_layers().unwrap();
+    assert_eq!((flat.width, flat.height), (2, 2));
+    assert_eq!(flat.data, vec![GlobalTile(1), GlobalTile(3), GlobalTile(3), GlobalTile::EMPTY]);
+    
+    level.layers.push(TileLayer::generate_from("small", 1, 2, |_, _| GlobalTile(4)).to_layer());
+    let error = level.overlay_tile_layers().unwrap_err();
+    assert_eq!(error.to_string(), "Layers have different sizes, expected 2x2 but found 1x2");
+}
@@ -1,48 +1,532 @@
-#![feature(custom_derive, plugin)]
-#![plugin(serde_macros)]
+//! Loads maps saved by the Tiled editor in its JSON format.
+//!
+//! Loading is safe to use on untrusted files, e.g. maps uploaded to a
+//! server: no input makes parsing a level or tileset panic or abort, and
+//! every problem with the file comes back as an `Err`. Layers have to be
+//! stored in memory, so the data a file can make the loader allocate is
+//! limited by the size of the file, except that lenient loading won't pad a
+//! layer past `level::MAX_PADDED_LAYER_TILES` tiles. The `fuzz` directory
+//! has `cargo fuzz` targets which check this.
+//!
+//! Loaded data is `Send` and `Sync`, so a level can be loaded on a worker
+//! thread and handed to another, or shared between threads behind an `Arc`.
+//! Tilesets are shared between clones of a level with `Arc` for the same
+//! reason. Anything added to the loaded types has to keep this true, which
+//! the `loaded_data_is_send_and_sync` test checks.
 
 extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_json;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
 use serde::{Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use layer::PolyPoint;
 
+// Declared first so its macros are available to the other modules
+#[macro_use]
+mod instrument;
+
+pub mod color;
+pub mod dependencies;
+pub mod error;
+pub mod format;
 pub mod layer;
 pub mod level;
+pub mod options;
+pub mod properties;
 pub mod tileset;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+mod schema;
+
+/// Bit set in a gid when its tile is flipped horizontally
+pub const FLIPPED_HORIZONTALLY_FLAG: u32 = 0x80000000;
+/// Bit set in a gid when its tile is flipped vertically
+pub const FLIPPED_VERTICALLY_FLAG: u32 = 0x40000000;
+/// Bit set in a gid when its tile is flipped diagonally (x and y swapped)
+pub const FLIPPED_DIAGONALLY_FLAG: u32 = 0x20000000;
+/// All of the flip flag bits, which must be masked off to get the tile id
+pub const FLIP_FLAGS: u32 = FLIPPED_HORIZONTALLY_FLAG | FLIPPED_VERTICALLY_FLAG | FLIPPED_DIAGONALLY_FLAG;
+
+/// A tile id as stored in layer data, unique across all of a map's tilesets.
+///
+/// The value as stored is the *raw* gid, whose top three bits are the flip
+/// flags (see `FLIP_FLAGS`). The *clean* gid, from `strip_flags`, has those
+/// bits cleared and is the one that identifies a tile. Two cells showing the
+/// same tile flipped differently have different raw gids but the same clean gid.
+///
+/// Equality, hashing and ordering are all over the raw gid. A flipped tile is
+/// not equal to the same tile unflipped, and since the flags are the top bits
+/// every flipped gid sorts after every unflipped one. Code which cares about
+/// *which tile* rather than *how a cell is drawn* should use `canonical` first.
+/// This crate follows that split: things which say what is drawn, like layer
+/// data and `Object::gid`, keep the flags, while sets of tiles such as
+/// `Level::all_tile_gids` only ever hold canonical gids.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GlobalTile(pub u32);
 
 impl GlobalTile {
+    /// The gid used in layer data for cells which don't contain a tile
+    pub const EMPTY: GlobalTile = GlobalTile(0);
+    
+    /// Whether this gid represents the absence of a tile
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    
+    /// The clean gid, with the flip flags cleared
+    pub fn strip_flags(self) -> GlobalTile {
+        GlobalTile(self.0 & !FLIP_FLAGS)
+    }
+    
+    /// The gid identifying the tile regardless of how it's flipped, for
+    /// sorting, deduplicating or looking up tiles. This is the clean gid, so
+    /// `gid.canonical() == gid.strip_flags()`.
+    pub fn canonical(self) -> GlobalTile {
+        self.strip_flags()
+    }
+    
+    /// The raw gid, including any flip flags
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+    
     /// From this GlobalTile, given the set of tilesets associated with the
     /// map, find the Tileset and LocalTile this ID belongs to, or None
     /// if it does not belong to any.
-    pub fn find_local(self, sets: &[tileset::Tileset]) -> Option<(usize, LocalTile)> {
-        for (i, set) in sets.iter().enumerate() {
-            if set.contains_tile(self) {
-                let id = LocalTile(self.0 - set.firstgid.0);
-                return Some((i, id))
+    ///
+    /// `sets` must be in ascending `firstgid` order, which is how Tiled writes
+    /// them. See `Level::validate_tileset_order` to check a loaded level.
+    /// Either a level's shared tilesets or plain `Tileset`s can be searched.
+    /// Flip flags are ignored.
+    pub fn find_local<S: Borrow<tileset::Tileset>>(self, sets: &[S]) -> Option<(usize, LocalTile)> {
+        let gid = self.strip_flags();
+        for (i, set) in sets.iter().map(Borrow::borrow).enumerate() {
+            if set.contains_tile(gid) {
+                if let Some(id) = gid.0.checked_sub(set.firstgid.0) {
+                    return Some((i, LocalTile(id)))
+                }
             }
         }
         None
     }
 }
 
-impl Deserialize for GlobalTile {
-    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+impl<'de> Deserialize<'de> for GlobalTile {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        
         // These are just wrapper structs, the values
         // should be decoded as a plain u32
-        Ok(GlobalTile(try!(u32::deserialize(d))))
+        let value = JsonValue::deserialize(d)?;
+        match tile_id_from_json(&value) {
+            Ok(id) => Ok(GlobalTile(id)),
+            Err(e) => Err(D::Error::custom(e)),
+        }
     }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct LocalTile(pub u32);
 
-impl Deserialize for LocalTile {
-    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+impl<'de> Deserialize<'de> for LocalTile {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        
         // These are just wrapper structs, the values
         // should be decoded as a plain u32
-        Ok(LocalTile(try!(u32::deserialize(d))))
+        let value = JsonValue::deserialize(d)?;
+        match tile_id_from_json(&value) {
+            Ok(id) => Ok(LocalTile(id)),
+            Err(e) => Err(D::Error::custom(e)),
+        }
+    }
+}
+
+/// Reads a tile id from JSON. Besides integers, this accepts floats with no
+/// fractional part and numeric strings, which some tools that post-process
+/// maps write instead.
+fn tile_id_from_json(value: &JsonValue) -> Result<u32, String> {
+    let id = match *value {
+        JsonValue::Number(ref id) => id.as_f64().unwrap_or(f64::NAN),
+        JsonValue::String(ref text) => match text.trim().parse::<f64>() {
+            Ok(id) => id,
+            Err(_) => return Err(format!("`{}` is not a tile id", text)),
+        },
+        ref other => return Err(format!("expected a tile id, found {:?}", other)),
+    };
+    
+    if !id.is_finite() || id.fract() != 0.0 {
+        Err(format!("{} is not a whole number", id))
+    } else if id < 0.0 {
+        Err(format!("{} is negative, tile ids can't be negative", id))
+    } else if id > u32::MAX as f64 {
+        Err(format!("{} is too large to be a tile id", id))
+    } else {
+        Ok(id as u32)
+    }
+}
+
+/// How deeply arrays and objects may be nested in a file. serde_json parses
+/// recursively, so without a limit a hostile file could overflow the stack,
+/// which aborts instead of panicking. Tiled never nests more than a few levels.
+const MAX_NESTING: usize = 128;
+
+/// Parses JSON from a file which may not be trustworthy, rejecting it first if
+/// it is nested more than `MAX_NESTING` levels deep
+pub(crate) fn parse_json<T: DeserializeOwned>(json: &[u8]) -> Result<T, serde_json::Error> {
+    use serde::de::Error;
+    
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > MAX_NESTING {
+                    return Err(serde_json::Error::custom(format!("JSON is nested more than {} levels deep", MAX_NESTING)));
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    
+    serde_json::from_slice(json)
+}
+
+/// Reads the JSON file at `path` using `parse_json`
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, serde_json::Error> {
+    parse_json(&read_file(path)?)
+}
+
+/// Reads the whole file at `path`
+pub(crate) fn read_file(path: &Path) -> Result<Vec<u8>, serde_json::Error> {
+    let mut bytes = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).map_err(serde_json::Error::io)?;
+    Ok(bytes)
+}
+
+/// Which ways a tile is flipped, as stored in the high bits of its gid
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TileFlip {
+    pub horizontal: bool,
+    pub vertical: bool,
+    /// Set when the x and y axes are swapped, which combined with the other
+    /// flags is how Tiled stores rotation
+    pub diagonal: bool,
+}
+
+impl TileFlip {
+    /// Reads the flip flags from a gid
+    pub fn from_gid(gid: GlobalTile) -> TileFlip {
+        TileFlip {
+            horizontal: gid.0 & FLIPPED_HORIZONTALLY_FLAG != 0,
+            vertical: gid.0 & FLIPPED_VERTICALLY_FLAG != 0,
+            diagonal: gid.0 & FLIPPED_DIAGONALLY_FLAG != 0,
+        }
+    }
+    
+    /// `gid` with its flip flags replaced by these ones
+    pub fn apply_to(self, gid: GlobalTile) -> GlobalTile {
+        let mut flags = 0;
+        if self.horizontal { flags |= FLIPPED_HORIZONTALLY_FLAG; }
+        if self.vertical { flags |= FLIPPED_VERTICALLY_FLAG; }
+        if self.diagonal { flags |= FLIPPED_DIAGONALLY_FLAG; }
+        GlobalTile(gid.strip_flags().0 | flags)
+    }
+    
+    /// Flips the texture coordinates of a tile's quad, given in the order
+    /// top-left, top-right, bottom-right, bottom-left of the quad on screen,
+    /// so that drawing the quad shows the tile the way Tiled does. The
+    /// diagonal flip swaps the x and y axes of the tile image (flipping it
+    /// across its top-left to bottom-right diagonal) and is applied before
+    /// the horizontal and vertical flips.
+    pub fn apply_to_rect_uv(&self, uv: [PolyPoint; 4]) -> [PolyPoint; 4] {
+        // Each corner as (right, bottom), in the order of `uv`
+        const CORNERS: [(bool, bool); 4] = [(false, false), (true, false), (true, true), (false, true)];
+        
+        // The image corner shown at a screen corner is found by undoing the
+        // flips in reverse order
+        let source = |(right, bottom): (bool, bool)| {
+            let (right, bottom) = (right != self.horizontal, bottom != self.vertical);
+            let (right, bottom) = if self.diagonal { (bottom, right) } else { (right, bottom) };
+            match (right, bottom) {
+                (false, false) => uv[0],
+                (true, false) => uv[1],
+                (true, true) => uv[2],
+                (false, true) => uv[3],
+            }
+        };
+        [source(CORNERS[0]), source(CORNERS[1]), source(CORNERS[2]), source(CORNERS[3])]
+    }
+    
+    /// The flags for a tile with these flags after `matrix` is applied to it,
+    /// or None if the result can't be expressed with flags because `matrix`
+    /// isn't a quarter turn or a mirror
+    pub(crate) fn transformed(self, matrix: &[[f32; 2]; 2]) -> Option<TileFlip> {
+        let mut m = [[0i32; 2]; 2];
+        for (row, values) in m.iter_mut().zip(matrix) {
+            for (cell, &value) in row.iter_mut().zip(values) {
+                *cell = match value {
+                    1.0 => 1,
+                    -1.0 => -1,
+                    0.0 => 0,
+                    _ => return None,
+                };
+            }
+        }
+        // Exactly one non-zero entry in each row and column
+        let straight = m[0][0] != 0 && m[1][1] != 0 && m[0][1] == 0 && m[1][0] == 0;
+        let swapped = m[0][0] == 0 && m[1][1] == 0 && m[0][1] != 0 && m[1][0] != 0;
+        if !straight && !swapped {
+            return None;
+        }
+        
+        // Tiled applies the diagonal flip first, then the other two
+        let h = if self.horizontal { -1 } else { 1 };
+        let v = if self.vertical { -1 } else { 1 };
+        let tile = if self.diagonal { [[0, h], [v, 0]] } else { [[h, 0], [0, v]] };
+        let n = [
+            [m[0][0] * tile[0][0] + m[0][1] * tile[1][0], m[0][0] * tile[0][1] + m[0][1] * tile[1][1]],
+            [m[1][0] * tile[0][0] + m[1][1] * tile[1][0], m[1][0] * tile[0][1] + m[1][1] * tile[1][1]],
+        ];
+        Some(if n[0][0] != 0 {
+            TileFlip { horizontal: n[0][0] < 0, vertical: n[1][1] < 0, diagonal: false }
+        } else {
+            TileFlip { horizontal: n[0][1] < 0, vertical: n[1][0] < 0, diagonal: true }
+        })
+    }
+}
+
+/// Position of a tile within a level, measured in tiles from the top-left
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TileCoord {
+    pub x: u32,
+    pub y: u32,
+}
+
+/// An affine transform of positions measured in tiles: a point `p` is moved
+/// to `matrix * p + translation`, where `matrix` is given row by row
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform2D {
+    pub matrix: [[f32; 2]; 2],
+    pub translation: [f32; 2],
+}
+
+impl Transform2D {
+    pub const IDENTITY: Transform2D = Transform2D {
+        matrix: [[1.0, 0.0], [0.0, 1.0]],
+        translation: [0.0, 0.0],
+    };
+    
+    /// Where `point` is moved to
+    pub fn apply(&self, point: [f32; 2]) -> [f32; 2] {
+        let m = &self.matrix;
+        [
+            m[0][0] * point[0] + m[0][1] * point[1] + self.translation[0],
+            m[1][0] * point[0] + m[1][1] * point[1] + self.translation[1],
+        ]
     }
+    
+    /// The transform which undoes this one, or None if there isn't one
+    /// because the matrix flattens everything onto a line or isn't finite
+    pub fn inverse(&self) -> Option<Transform2D> {
+        let m = &self.matrix;
+        let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        if det == 0.0 || !det.is_finite() || !self.translation.iter().all(|t| t.is_finite()) {
+            return None;
+        }
+        
+        let matrix = [
+            [m[1][1] / det, -m[0][1] / det],
+            [-m[1][0] / det, m[0][0] / det],
+        ];
+        let t = &self.translation;
+        Some(Transform2D {
+            matrix,
+            translation: [
+                -(matrix[0][0] * t[0] + matrix[0][1] * t[1]),
+                -(matrix[1][0] * t[0] + matrix[1][1] * t[1]),
+            ],
+        })
+    }
+}
+
+/// Resolves a path written inside a map or tileset file against the location
+/// of that file, the same way Tiled does. `base_map` is the path of the file
+/// containing the reference (not its directory).
+///
+/// Absolute references are used as-is. `.` components are dropped and `..`
+/// components remove the directory before them where there is one, so
+/// `maps/level.json` referencing `../tilesets/a.json` gives `tilesets/a.json`.
+/// Trailing separators are ignored. Tiled always writes `/` as the separator,
+/// which is understood on every platform.
+pub fn resolve_relative(base_map: &Path, reference: &str) -> PathBuf {
+    let reference = Path::new(reference);
+    let joined = match base_map.parent() {
+        Some(dir) if !reference.is_absolute() => dir.join(reference),
+        _ => reference.to_path_buf(),
+    };
+    
+    let mut resolved = PathBuf::new();
+    // Number of trailing normal components which a `..` is able to remove
+    let mut depth = 0;
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => {
+                resolved.pop();
+                depth -= 1;
+            }
+            // Nothing can be above the root
+            Component::ParentDir if resolved.has_root() => {}
+            Component::ParentDir => resolved.push(".."),
+            Component::Normal(part) => {
+                resolved.push(part);
+                depth += 1;
+            }
+            Component::Prefix(_) | Component::RootDir => resolved.push(component.as_os_str()),
+        }
+    }
+    resolved
+}
+
+/// The reverse of `resolve_relative`: the reference to write in the file at
+/// `base_map` so that it points at `target`, using `/` as the separator.
+/// Both paths should be relative to the same directory, or both absolute.
+pub fn relative_path(base_map: &Path, target: &Path) -> String {
+    let base_dir = resolve_relative(base_map, ".");
+    let target = resolve_relative(Path::new(""), &target.to_string_lossy());
+    
+    let base: Vec<Component> = base_dir.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = base.iter().zip(&target).take_while(|&(a, b)| a == b).count();
+    
+    let mut parts: Vec<String> = Vec::new();
+    for _ in common..base.len() {
+        parts.push("..".to_string());
+    }
+    for component in &target[common..] {
+        parts.push(component.as_os_str().to_string_lossy().into_owned());
+    }
+    parts.join("/")
+}
+
+#[test]
+fn resolve_relative_layouts() {
+    let cases = [
+        ("maps/level.json", "tiles.json", "maps/tiles.json"),
+        ("maps/level.json", "./tiles.json", "maps/tiles.json"),
+        ("maps/level.json", "../tilesets/tiles.json", "tilesets/tiles.json"),
+        ("maps/a/b/level.json", "../../tiles.json", "maps/tiles.json"),
+        ("maps/level.json", "sub/./x/../tiles.json", "maps/sub/tiles.json"),
+        ("maps/level.json", "sub/dir/", "maps/sub/dir"),
+        ("maps/level.json", "../../outside.json", "../outside.json"),
+        ("maps/level.json", "/abs/tiles.json", "/abs/tiles.json"),
+        ("/root/maps/level.json", "../../../../tiles.json", "/tiles.json"),
+        ("level.json", "tiles.json", "tiles.json"),
+        ("level.json", "../tiles.json", "../tiles.json"),
+        ("", "tiles.json", "tiles.json"),
+        // A tileset one directory above the map...
+        ("game/maps/level.json", "../tileset.json", "game/tileset.json"),
+        // ...referencing an image one directory above itself
+        ("game/tileset.json", "../images/tiles.png", "images/tiles.png"),
+    ];
+    
+    for &(base, reference, expected) in cases.iter() {
+        assert_eq!(
+            resolve_relative(Path::new(base), reference), PathBuf::from(expected),
+            "resolving `{}` from `{}`", reference, base
+        );
+    }
+    
+    // Going back the other way gives a reference which resolves to the same path
+    for &(base, reference, _) in cases.iter().filter(|case| !case.1.starts_with('/')) {
+        let target = resolve_relative(Path::new(base), reference);
+        let back = relative_path(Path::new(base), &target);
+        assert_eq!(resolve_relative(Path::new(base), &back), target, "relative path `{}` from `{}`", back, base);
+    }
+}
+
+#[test]
+fn lenient_tile_ids() {
+    use serde_json::from_str;
+    
+    let tiles: Vec<GlobalTile> = from_str(r#"[0, "1540073", 1540073.0, " 7 "]"#).unwrap();
+    assert_eq!(tiles, vec![GlobalTile(0), GlobalTile(1540073), GlobalTile(1540073), GlobalTile(7)]);
+    
+    assert!(from_str::<GlobalTile>("-1").is_err());
+    assert!(from_str::<GlobalTile>("1.5").is_err());
+    assert!(from_str::<GlobalTile>(r#""grass""#).is_err());
+    assert!(from_str::<GlobalTile>("4294967296").is_err());
+}
+
+#[test]
+fn loaded_data_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    
+    assert_send_sync::<level::Level>();
+    assert_send_sync::<level::PropertyRef>();
+    assert_send_sync::<layer::Layer>();
+    assert_send_sync::<layer::TileLayer>();
+    assert_send_sync::<layer::ObjectLayer>();
+    assert_send_sync::<layer::ImageLayer>();
+    assert_send_sync::<layer::Object>();
+    assert_send_sync::<tileset::Tileset>();
+    assert_send_sync::<properties::Properties>();
+    assert_send_sync::<format::FormatProfile>();
+    assert_send_sync::<dependencies::Dependencies>();
+    assert_send_sync::<options::LoadOptions>();
+    assert_send_sync::<error::TiledError>();
+}
+
+#[test]
+fn flipped_uvs() {
+    let point = |x, y| PolyPoint { x, y };
+    let uv = [point(0.0, 0.0), point(1.0, 0.0), point(1.0, 1.0), point(0.0, 1.0)];
+    
+    let mirrored = TileFlip { horizontal: true, ..TileFlip::default() };
+    assert_eq!(mirrored.apply_to_rect_uv(uv), [uv[1], uv[0], uv[3], uv[2]]);
+    // Tiled stores a quarter turn clockwise as diagonal + horizontal, which
+    // puts the image's bottom-left corner at the top-left
+    let turned = TileFlip { horizontal: true, vertical: false, diagonal: true };
+    assert_eq!(turned.apply_to_rect_uv(uv), [uv[3], uv[0], uv[1], uv[2]]);
+}
+
+#[test]
+fn flipped_gid_ordering() {
+    use std::collections::HashSet;
+    
+    let flipped = TileFlip { horizontal: true, vertical: false, diagonal: false }.apply_to(GlobalTile(17));
+    assert!(flipped != GlobalTile(17));
+    assert_eq!(flipped.canonical(), GlobalTile(17));
+    
+    let mut gids = vec![flipped, GlobalTile(18), GlobalTile(17)];
+    gids.sort();
+    assert_eq!(gids, vec![GlobalTile(17), GlobalTile(18), flipped]);
+    
+    let tiles: HashSet<GlobalTile> = gids.iter().map(|gid| gid.canonical()).collect();
+    assert_eq!(tiles.len(), 2);
 }
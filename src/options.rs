@@ -0,0 +1,48 @@
+/// Options controlling how a level is checked and corrected while loading.
+/// `LoadOptions::default()` is what `Level::load` uses.
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    /// What to do with tile layer gids which don't refer to a tile in any of
+    /// the level's tilesets, e.g. because the tileset they came from was
+    /// removed in the editor.
+    pub gid_integrity: GidIntegrity,
+    /// When a tile layer's data doesn't have exactly `width * height` tiles,
+    /// pad it with empty tiles or cut it short and record a warning, instead
+    /// of failing to load. Useful for salvaging partially corrupted maps.
+    /// Layers declared larger than `level::MAX_PADDED_LAYER_TILES` still fail.
+    pub lenient_layer_data: bool,
+    /// Fail to load levels with a tileset whose `firstgid` is out of range
+    /// (see `Tileset::firstgid_problem`), instead of recording a warning
+    pub strict_firstgid: bool,
+    /// Give properties which have no type, as in maps saved before Tiled 1.2,
+    /// the type their value looks like (see `properties::infer_type`) so that
+    /// `Properties::get_value` reads old and new maps alike. Each property
+    /// given a type is recorded as a warning.
+    pub coerce_legacy_properties: bool,
+    /// Load as much of a map as possible when parts of it can't be loaded,
+    /// such as group layers, encoded tile data or tilesets which can't be
+    /// read, instead of failing. A layer which can't be loaded becomes an
+    /// empty tile layer with its name, a tileset becomes a
+    /// `Tileset::placeholder` with its `firstgid`, and each one replaced is
+    /// recorded as a warning saying why. Errors in the rest of the map still
+    /// fail.
+    pub allow_partial: bool,
+    /// Remember where in the JSON each layer, object and tileset was loaded
+    /// from, so that `source_pointer` can give its JSON pointer and
+    /// `Level::apply_json_patch` can find it by one. Off by default.
+    pub record_source_pointers: bool,
+    /// Keep the JSON of every object in `Object::raw`, so that keys added by
+    /// editor plugins can be read after loading. Off by default, when objects
+    /// store nothing extra.
+    pub keep_raw_objects: bool,
+}
+
+/// How to handle tile layer gids which don't belong to any tileset
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GidIntegrity {
+    /// Replace the gid with 0 (no tile) and record a warning on the level
+    #[default]
+    Clear,
+    /// Fail to load the level
+    Error,
+}
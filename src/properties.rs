@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+use serde::{Deserialize, Deserializer};
+use serde_json::Map as JsonMap;
+use serde_json::Value as JsonValue;
+use color::Color;
+
+/// Custom properties set on a map, layer, object, tileset or tile in the editor.
+/// This dereferences to the map of property names to values for reading, and
+/// adds typed getters for the common parse-or-default cases. Changes go
+/// through `set` and `unset` so that the types stay in step with the values.
+///
+/// This is the one representation of properties for everything that has
+/// them, so that the same getters work everywhere; new kinds of owner should
+/// use it too, with `#[serde(default)]` when Tiled may leave it out. Where
+/// properties are inherited, the owner's own properties are layered over the
+/// inherited ones with `merged_over`.
+///
+/// Both of the formats Tiled writes can be loaded: an object of names to
+/// values, and since Tiled 1.2 a list of `{"name", "type", "value"}` objects.
+/// Values which aren't strings are stored as their JSON text, so `true` is
+/// stored as `"true"`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Properties {
+    map: BTreeMap<String, String>,
+    /// The type of each property, when the list format declared one
+    types: BTreeMap<String, String>,
+}
+
+impl Properties {
+    pub const fn new() -> Properties {
+        Properties { map: BTreeMap::new(), types: BTreeMap::new() }
+    }
+    
+    /// The type Tiled gave a property, such as `"int"` or `"color"`. Only
+    /// properties loaded from the list format have one.
+    pub fn get_type(&self, key: &str) -> Option<&str> {
+        self.types.get(key).map(|kind| &kind[..])
+    }
+    
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.map.get(key).map(|value| &value[..])
+    }
+    
+    /// Reads a boolean property, accepting "true", "false", "1" and "0"
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get_str(key) {
+            Some("true") | Some("1") => Some(true),
+            Some("false") | Some("0") => Some(false),
+            _ => None,
+        }
+    }
+    
+    pub fn get_i64(&self, key: &str) -> Option<i64> {
+        self.get_str(key).and_then(|value| value.parse().ok())
+    }
+    
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.get_str(key).and_then(|value| value.parse().ok())
+    }
+    
+    /// Reads a property as the type Tiled gave it, see `PropertyValue`.
+    /// Properties without a type, or whose value doesn't parse as their
+    /// type, are read as strings.
+    pub fn get_value(&self, key: &str) -> Option<PropertyValue<'_>> {
+        let text = self.get_str(key)?;
+        let value = match self.get_type(key) {
+            Some("bool") => self.get_bool(key).map(PropertyValue::Bool),
+            Some("int") => self.get_i64(key).map(PropertyValue::Int),
+            Some("float") => self.get_f64(key).map(PropertyValue::Float),
+            Some("color") => self.get_color(key).map(PropertyValue::Color),
+            Some("file") => Some(PropertyValue::File(text)),
+            Some("object") => text.parse().ok().map(PropertyValue::Object),
+            _ => None,
+        };
+        Some(value.unwrap_or(PropertyValue::String(text)))
+    }
+    
+    /// Gives each property which has no type the type its value looks like,
+    /// following the rules of `infer_type`, so that `get_value` reads it as
+    /// that type. Returns the names of the properties given a type along with
+    /// the type.
+    pub(crate) fn coerce_untyped(&mut self) -> Vec<(String, &'static str)> {
+        let mut coerced = Vec::new();
+        for (key, value) in &self.map {
+            if self.types.contains_key(key) {
+                continue;
+            }
+            if let Some(kind) = infer_type(value) {
+                coerced.push((key.clone(), kind));
+            }
+        }
+        for &(ref key, kind) in &coerced {
+            self.types.insert(key.clone(), kind.to_string());
+        }
+        coerced
+    }
+    
+    /// Reads a color property in Tiled's `#RRGGBB` or `#AARRGGBB` format
+    pub fn get_color(&self, key: &str) -> Option<Color> {
+        self.get_str(key).and_then(Color::parse)
+    }
+    
+    /// Sets a property, forgetting any type it was declared with since the new
+    /// value may not be of that type
+    pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        self.types.remove(&key);
+        self.map.insert(key, value.into());
+    }
+    
+    /// Sets a property, keeping any type it was declared with, for when the
+    /// new value is known to be of the same kind
+    pub(crate) fn replace_value(&mut self, key: &str, value: String) {
+        self.map.insert(key.to_string(), value);
+    }
+    
+    /// Copies the property `key` from `other` along with its type, replacing
+    /// any property with that name
+    pub fn copy_from(&mut self, other: &Properties, key: &str) {
+        if let Some(value) = other.map.get(key) {
+            self.map.insert(key.to_string(), value.clone());
+            match other.types.get(key) {
+                Some(kind) => self.types.insert(key.to_string(), kind.clone()),
+                None => self.types.remove(key),
+            };
+        }
+    }
+    
+    /// The type `key` was declared with, if its value can't be read as that
+    /// type by `get_value`. Strings, files and types this crate doesn't know
+    /// take any value.
+    pub(crate) fn type_mismatch(&self, key: &str) -> Option<&str> {
+        let kind = self.get_type(key)?;
+        let typed = matches!(kind, "bool" | "int" | "float" | "color" | "object");
+        match self.get_value(key) {
+            Some(PropertyValue::String(_)) if typed => Some(kind),
+            _ => None,
+        }
+    }
+    
+    /// Removes a property along with its type, returning its value
+    pub fn unset(&mut self, key: &str) -> Option<String> {
+        self.types.remove(key);
+        self.map.remove(key)
+    }
+    
+    /// These properties with any missing ones filled in from `parent`, for
+    /// looking properties up through a chain such as object, layer, map
+    pub fn merged_over(&self, parent: &Properties) -> Properties {
+        let mut merged = parent.clone();
+        for (key, value) in &self.map {
+            merged.map.insert(key.clone(), value.clone());
+            match self.types.get(key) {
+                Some(kind) => merged.types.insert(key.clone(), kind.clone()),
+                None => merged.types.remove(key),
+            };
+        }
+        merged
+    }
+    
+    /// Rewrites object-typed properties (ones the list format declared with
+    /// type `"object"`) which refer to an id in `ids` to refer to the new id
+    pub(crate) fn remap_object_refs(&mut self, ids: &HashMap<u32, u32>) {
+        for (key, value) in self.map.iter_mut() {
+            if self.types.get(key).map(|kind| &kind[..]) != Some("object") {
+                continue;
+            }
+            let new_id = value.parse().ok().and_then(|id| ids.get(&id));
+            if let Some(new_id) = new_id {
+                *value = new_id.to_string();
+            }
+        }
+    }
+    
+    /// The properties as JSON the way Tiled writes them. With no types this
+    /// is an object of strings, as in maps from before Tiled 1.2. Otherwise
+    /// it is a list of `{"name", "type", "value"}` objects, where properties
+    /// without a type are strings and values are written as their type.
+    pub fn to_json_value(&self) -> JsonValue {
+        if self.types.is_empty() {
+            return JsonValue::Object(self.map.iter().map(|(key, value)| {
+                (key.clone(), JsonValue::String(value.clone()))
+            }).collect());
+        }
+        
+        JsonValue::Array(self.map.iter().map(|(key, text)| {
+            let value = match self.get_value(key) {
+                Some(PropertyValue::Bool(flag)) => JsonValue::Bool(flag),
+                Some(PropertyValue::Int(n)) => JsonValue::from(n),
+                Some(PropertyValue::Float(n)) if n.is_finite() => JsonValue::from(n),
+                Some(PropertyValue::Object(id)) => JsonValue::from(id),
+                _ => JsonValue::String(text.clone()),
+            };
+            let mut entry = JsonMap::new();
+            entry.insert("name".to_string(), JsonValue::String(key.clone()));
+            entry.insert("type".to_string(), JsonValue::String(self.get_type(key).unwrap_or("string").to_string()));
+            entry.insert("value".to_string(), value);
+            JsonValue::Object(entry)
+        }).collect())
+    }
+    
+    pub fn get_str_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get_str(key).unwrap_or(default)
+    }
+    
+    pub fn get_bool_or(&self, key: &str, default: bool) -> bool {
+        self.get_bool(key).unwrap_or(default)
+    }
+    
+    pub fn get_i64_or(&self, key: &str, default: i64) -> i64 {
+        self.get_i64(key).unwrap_or(default)
+    }
+    
+    pub fn get_f64_or(&self, key: &str, default: f64) -> f64 {
+        self.get_f64(key).unwrap_or(default)
+    }
+    
+    pub fn get_color_or(&self, key: &str, default: Color) -> Color {
+        self.get_color(key).unwrap_or(default)
+    }
+}
+
+impl Deref for Properties {
+    type Target = BTreeMap<String, String>;
+    
+    fn deref(&self) -> &BTreeMap<String, String> {
+        &self.map
+    }
+}
+
+impl From<BTreeMap<String, String>> for Properties {
+    fn from(map: BTreeMap<String, String>) -> Properties {
+        Properties { map, types: BTreeMap::new() }
+    }
+}
+
+impl<'de> Deserialize<'de> for Properties {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        
+        let mut properties = Properties::new();
+        match JsonValue::deserialize(d)? {
+            JsonValue::Object(values) => {
+                for (name, value) in values {
+                    properties.map.insert(name, property_text(value));
+                }
+            }
+            JsonValue::Array(list) => {
+                for (i, entry) in list.into_iter().enumerate() {
+                    let mut entry = match entry {
+                        JsonValue::Object(entry) => entry,
+                        _ => return Err(D::Error::custom(format!("property {} is not an object", i))),
+                    };
+                    let name = match entry.remove("name") {
+                        Some(JsonValue::String(name)) => name,
+                        _ => return Err(D::Error::custom(format!("property {} has no name", i))),
+                    };
+                    if let Some(JsonValue::String(kind)) = entry.remove("type") {
+                        properties.types.insert(name.clone(), kind);
+                    }
+                    let value = entry.remove("value").unwrap_or(JsonValue::Null);
+                    properties.map.insert(name, property_text(value));
+                }
+            }
+            JsonValue::Null => {}
+            _ => return Err(D::Error::custom("properties must be an object or a list")),
+        }
+        Ok(properties)
+    }
+}
+
+/// A property value read as the type Tiled gave the property
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PropertyValue<'a> {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Color(Color),
+    /// A path, relative to the file the property is in
+    File(&'a str),
+    /// The id of an object in the same map
+    Object(u32),
+    String(&'a str),
+}
+
+/// The type an untyped property from an old map looks like it should have,
+/// or None if it should stay a string. The rules are deliberately strict so
+/// that strings are never turned into something else by accident:
+///
+/// - `"true"` and `"false"` are `bool`; other spellings like `"1"` or
+///   `"True"` aren't
+/// - Whole numbers are `int` if they fit in an i64, have no `+` sign and no
+///   leading zeros, so `"007"` and `"-0"` stay strings
+/// - Numbers with a decimal point, with digits on both sides of it and the
+///   same rules for the whole part, are `float`; exponents, `"inf"` and
+///   `"NaN"` aren't accepted
+/// - `#RRGGBB` and `#AARRGGBB` hex colors are `color`; they must start
+///   with `#`
+pub fn infer_type(value: &str) -> Option<&'static str> {
+    fn is_digits(text: &str) -> bool {
+        !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit())
+    }
+    
+    // An optional minus sign then digits, with no leading zeros
+    fn is_whole(text: &str) -> bool {
+        let digits = text.strip_prefix('-').unwrap_or(text);
+        is_digits(digits) && (digits == "0" || !digits.starts_with('0'))
+    }
+    
+    if value == "true" || value == "false" {
+        return Some("bool");
+    }
+    if value.starts_with('#') && Color::parse(value).is_some() {
+        return Some("color");
+    }
+    if value == "-0" {
+        return None;
+    }
+    if is_whole(value) {
+        return value.parse::<i64>().ok().map(|_| "int");
+    }
+    
+    let mut parts = value.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(whole), Some(fraction)) if is_whole(whole) && is_digits(fraction) => Some("float"),
+        _ => None,
+    }
+}
+
+/// The text stored for a property value
+pub(crate) fn property_text(value: JsonValue) -> String {
+    match value {
+        JsonValue::String(text) => text,
+        JsonValue::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[test]
+fn typed_getters() {
+    let mut props = Properties::new();
+    props.set("speed", "2.5");
+    props.set("count", "-3");
+    props.set("solid", "1");
+    props.set("tint", "#ff8000");
+    
+    assert_eq!(props.get_f64("speed"), Some(2.5));
+    assert_eq!(props.get_i64("count"), Some(-3));
+    assert_eq!(props.get_i64("speed"), None);
+    assert_eq!(props.get_bool("solid"), Some(true));
+    assert_eq!(props.get_bool("speed"), None);
+    assert_eq!(props.get_color("tint"), Some(Color { r: 0xFF, g: 0x80, b: 0, a: 0xFF }));
+    assert_eq!(props.get_f64_or("missing", 1.0), 1.0);
+    assert_eq!(props.get_str_or("missing", "none"), "none");
+}
+
+#[test]
+fn both_property_formats() {
+    use serde_json::from_str;
+    
+    let old: Properties = from_str(r#"{ "name": "door", "locked": true, "keys": 2 }"#).unwrap();
+    let new: Properties = from_str(r#"[
+        { "name": "name", "type": "string", "value": "door" },
+        { "name": "locked", "type": "bool", "value": true },
+        { "name": "keys", "type": "int", "value": 2 }
+    ]"#).unwrap();
+    
+    assert_eq!(*old, *new);
+    assert_eq!(new.get_bool("locked"), Some(true));
+    assert_eq!(new.get_i64("keys"), Some(2));
+    assert_eq!(new.get_type("keys"), Some("int"));
+    assert_eq!(old.get_type("keys"), None);
+}
+
+#[test]
+fn legacy_type_inference() {
+    let cases = [
+        ("true", Some("bool")), ("True", None), ("1", Some("int")),
+        ("-42", Some("int")), ("007", None), ("-0", None), ("+3", None),
+        ("99999999999999999999", None), ("3.5", Some("float")), ("-0.25", Some("float")),
+        ("5.", None), (".5", None), ("1e5", None), ("NaN", None),
+        ("#ff0000", Some("color")), ("#80ff0000", Some("color")), ("ff0000", None),
+        ("", None), ("hello", None),
+    ];
+    for &(value, expected) in cases.iter() {
+        assert_eq!(infer_type(value), expected, "inferring the type of `{}`", value);
+    }
+    
+    let mut old: Properties = ::serde_json::from_str(r#"{ "speed": 3.5, "name": "007" }"#).unwrap();
+    old.coerce_untyped();
+    assert_eq!(old.get_value("speed"), Some(PropertyValue::Float(3.5)));
+    assert_eq!(old.get_value("name"), Some(PropertyValue::String("007")));
+}
@@ -0,0 +1,289 @@
+//! A hand-written check of the structure of map JSON, which reports every
+//! missing or mistyped field by its JSON pointer rather than stopping at the
+//! first error like deserialization does.
+
+use serde_json::Map as JsonMap;
+use serde_json::Value as JsonValue;
+
+/// The kinds of JSON value a field can be required to have
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    /// A non-negative integer
+    Unsigned,
+    /// Any number
+    Number,
+    Str,
+    Bool,
+    Object,
+    Array,
+    /// Properties in either of the formats Tiled writes
+    Properties,
+}
+
+impl Kind {
+    fn matches(self, value: &JsonValue) -> bool {
+        match (self, value) {
+            (Kind::Unsigned, JsonValue::Number(n)) => n.is_u64(),
+            (Kind::Number, &JsonValue::Number(_)) => true,
+            (Kind::Str, &JsonValue::String(_)) => true,
+            (Kind::Bool, &JsonValue::Bool(_)) => true,
+            (Kind::Object, &JsonValue::Object(_)) => true,
+            (Kind::Array, &JsonValue::Array(_)) => true,
+            (Kind::Properties, &JsonValue::Object(_)) |
+            (Kind::Properties, &JsonValue::Array(_)) => true,
+            _ => false,
+        }
+    }
+    
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::Unsigned => "a non-negative integer",
+            Kind::Number => "a number",
+            Kind::Str => "a string",
+            Kind::Bool => "a boolean",
+            Kind::Object => "an object",
+            Kind::Array => "an array",
+            Kind::Properties => "an object or a list of properties",
+        }
+    }
+}
+
+/// Collects the problems found while walking the JSON
+struct Checker {
+    problems: Vec<String>,
+}
+
+impl Checker {
+    /// Checks that `value` is an object, returning its fields if it is
+    fn object<'a>(&mut self, path: &str, value: &'a JsonValue) -> Option<&'a JsonMap<String, JsonValue>> {
+        match *value {
+            JsonValue::Object(ref fields) => Some(fields),
+            _ => {
+                self.problems.push(format!("{} is not an object", path_or_root(path)));
+                None
+            }
+        }
+    }
+    
+    /// Checks a field which must be present
+    fn required<'a>(&mut self, path: &str, fields: &'a JsonMap<String, JsonValue>, key: &str, kind: Kind) -> Option<&'a JsonValue> {
+        match fields.get(key) {
+            Some(value) => self.check(path, key, value, kind),
+            None => {
+                self.problems.push(format!("{}/{} is missing", path, key));
+                None
+            }
+        }
+    }
+    
+    /// Checks a field which may be left out
+    fn optional<'a>(&mut self, path: &str, fields: &'a JsonMap<String, JsonValue>, key: &str, kind: Kind) -> Option<&'a JsonValue> {
+        match fields.get(key) {
+            Some(&JsonValue::Null) | None => None,
+            Some(value) => self.check(path, key, value, kind),
+        }
+    }
+    
+    fn check<'a>(&mut self, path: &str, key: &str, value: &'a JsonValue, kind: Kind) -> Option<&'a JsonValue> {
+        if kind.matches(value) {
+            Some(value)
+        } else {
+            self.problems.push(format!("{}/{} is not {}", path, key, kind.describe()));
+            None
+        }
+    }
+    
+    fn level(&mut self, value: &JsonValue) {
+        let fields = match self.object("", value) {
+            Some(fields) => fields,
+            None => return,
+        };
+        
+        for key in &["height", "width", "tileheight", "tilewidth"] {
+            self.required("", fields, key, Kind::Unsigned);
+        }
+        self.required("", fields, "orientation", Kind::Str);
+        self.required("", fields, "renderorder", Kind::Str);
+        self.required("", fields, "properties", Kind::Properties);
+        self.optional("", fields, "parallaxoriginx", Kind::Number);
+        self.optional("", fields, "parallaxoriginy", Kind::Number);
+        self.optional("", fields, "nextobjectid", Kind::Unsigned);
+        
+        if let Some(JsonValue::Array(layers)) = self.required("", fields, "layers", Kind::Array) {
+            for (i, layer) in layers.iter().enumerate() {
+                self.layer(&format!("/layers/{}", i), layer);
+            }
+        }
+        if let Some(JsonValue::Array(tilesets)) = self.required("", fields, "tilesets", Kind::Array) {
+            for (i, tileset) in tilesets.iter().enumerate() {
+                self.tileset(&format!("/tilesets/{}", i), tileset);
+            }
+        }
+    }
+    
+    fn layer(&mut self, path: &str, value: &JsonValue) {
+        let fields = match self.object(path, value) {
+            Some(fields) => fields,
+            None => return,
+        };
+        
+        self.required(path, fields, "name", Kind::Str);
+        self.optional(path, fields, "id", Kind::Unsigned);
+        self.required(path, fields, "opacity", Kind::Number);
+        self.optional(path, fields, "properties", Kind::Properties);
+        self.required(path, fields, "visible", Kind::Bool);
+        self.optional(path, fields, "locked", Kind::Bool);
+        for key in &["x", "y"] {
+            self.required(path, fields, key, Kind::Number);
+        }
+        for key in &["offsetx", "offsety", "parallaxx", "parallaxy"] {
+            self.optional(path, fields, key, Kind::Number);
+        }
+        
+        let kind = match self.required(path, fields, "type", Kind::Str) {
+            Some(JsonValue::String(kind)) => &kind[..],
+            _ => return,
+        };
+        // Image layers have no size
+        if kind != "imagelayer" {
+            self.required(path, fields, "width", Kind::Unsigned);
+            self.required(path, fields, "height", Kind::Unsigned);
+        }
+        match kind {
+            "tilelayer" => {
+                // CSV data can also be written as one string
+                let csv = fields.get("encoding").and_then(JsonValue::as_str) == Some("csv");
+                let data_kind = match fields.get("data") {
+                    Some(&JsonValue::String(_)) if csv => Kind::Str,
+                    _ => Kind::Array,
+                };
+                if let Some(JsonValue::Array(data)) = self.required(path, fields, "data", data_kind) {
+                    // Tile ids are loaded leniently, so only rule out values
+                    // which can never be one
+                    for (i, tile) in data.iter().enumerate() {
+                        if !(Kind::Number.matches(tile) || Kind::Str.matches(tile)) {
+                            self.problems.push(format!("{}/data/{} is not a tile id", path, i));
+                        }
+                    }
+                }
+            }
+            "objectgroup" => {
+                self.optional(path, fields, "color", Kind::Str);
+                self.required(path, fields, "draworder", Kind::Str);
+                if let Some(JsonValue::Array(objects)) = self.required(path, fields, "objects", Kind::Array) {
+                    for (i, object) in objects.iter().enumerate() {
+                        self.object_fields(&format!("{}/objects/{}", path, i), object);
+                    }
+                }
+            }
+            "imagelayer" => {
+                self.required(path, fields, "image", Kind::Str);
+                self.optional(path, fields, "repeatx", Kind::Bool);
+                self.optional(path, fields, "repeaty", Kind::Bool);
+            }
+            other => self.problems.push(format!("{}/type `{}` is not a known layer type", path, other)),
+        }
+    }
+    
+    fn object_fields(&mut self, path: &str, value: &JsonValue) {
+        let fields = match self.object(path, value) {
+            Some(fields) => fields,
+            None => return,
+        };
+        
+        self.required(path, fields, "id", Kind::Unsigned);
+        self.required(path, fields, "name", Kind::Str);
+        // Newer versions of Tiled write `class` instead of `type`
+        if fields.contains_key("type") || !fields.contains_key("class") {
+            self.required(path, fields, "type", Kind::Str);
+        } else {
+            self.required(path, fields, "class", Kind::Str);
+        }
+        self.optional(path, fields, "gid", Kind::Unsigned);
+        self.optional(path, fields, "ellipse", Kind::Bool);
+        self.required(path, fields, "properties", Kind::Properties);
+        self.required(path, fields, "visible", Kind::Bool);
+        for key in &["rotation", "height", "width", "x", "y"] {
+            self.required(path, fields, key, Kind::Number);
+        }
+        
+        if let Some(JsonValue::Array(points)) = self.optional(path, fields, "polygon", Kind::Array) {
+            for (i, point) in points.iter().enumerate() {
+                let point_path = format!("{}/polygon/{}", path, i);
+                if let Some(point) = self.object(&point_path, point) {
+                    self.required(&point_path, point, "x", Kind::Number);
+                    self.required(&point_path, point, "y", Kind::Number);
+                }
+            }
+        }
+    }
+    
+    fn tileset(&mut self, path: &str, value: &JsonValue) {
+        let fields = match self.object(path, value) {
+            Some(fields) => fields,
+            None => return,
+        };
+        
+        self.required(path, fields, "firstgid", Kind::Unsigned);
+        // The rest of an external tileset is in its own file
+        if fields.contains_key("source") {
+            self.required(path, fields, "source", Kind::Str);
+            return;
+        }
+        
+        self.required(path, fields, "name", Kind::Str);
+        self.required(path, fields, "image", Kind::Str);
+        for key in &["tilecount", "tileheight", "tilewidth", "columns", "imageheight", "imagewidth", "margin", "spacing"] {
+            self.required(path, fields, key, Kind::Unsigned);
+        }
+        self.optional(path, fields, "properties", Kind::Properties);
+        self.required(path, fields, "tileproperties", Kind::Object);
+        self.required(path, fields, "tiles", Kind::Object);
+        
+        if let Some(JsonValue::Array(terrains)) = self.optional(path, fields, "terrains", Kind::Array) {
+            for (i, terrain) in terrains.iter().enumerate() {
+                let terrain_path = format!("{}/terrains/{}", path, i);
+                if let Some(terrain) = self.object(&terrain_path, terrain) {
+                    self.required(&terrain_path, terrain, "name", Kind::Str);
+                    self.required(&terrain_path, terrain, "tile", Kind::Unsigned);
+                }
+            }
+        }
+    }
+}
+
+fn path_or_root(path: &str) -> &str {
+    if path.is_empty() { "The document" } else { path }
+}
+
+/// Checks that a parsed map has every field needed to load it, with the
+/// right types, returning a message for each problem found
+pub(crate) fn check_level(value: &JsonValue) -> Vec<String> {
+    let mut checker = Checker { problems: Vec::new() };
+    checker.level(value);
+    checker.problems
+}
+
+#[test]
+fn schema_problems() {
+    use serde_json;
+    
+    let data = include_str!("../test-assets/levels/simple2.json");
+    assert_eq!(check_level(&serde_json::from_str(data).unwrap()), Vec::<String>::new());
+    
+    let broken: JsonValue = serde_json::from_str(r#"{
+        "height": 2, "width": -2, "tileheight": 32, "tilewidth": 32,
+        "orientation": "orthogonal", "properties": {},
+        "layers": [{
+            "type": "tilelayer", "name": "ground", "opacity": 1, "visible": true,
+            "width": 2, "height": 2, "x": 0, "y": 0, "data": {}
+        }],
+        "tilesets": [{ "firstgid": 1, "source": 7 }]
+    }"#).unwrap();
+    assert_eq!(check_level(&broken), vec![
+        "/width is not a non-negative integer",
+        "/renderorder is missing",
+        "/layers/0/data is not an array",
+        "/tilesets/0/source is not a string",
+    ]);
+}
@@ -1,20 +1,24 @@
 use std::str;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
-use std::fs::File;
-use std::collections::HashMap;
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 
-use {GlobalTile, LocalTile};
+use {GlobalTile, LocalTile, FLIP_FLAGS, parse_json, read_json, relative_path, resolve_relative};
+use error::{TiledError, from_value_at_field};
+use properties::Properties;
 
 use serde::{Deserialize, Deserializer};
 
 use serde_json;
+use serde_json::Map as JsonMap;
 use serde_json::Value as JsonValue;
 use serde_json::Error as JsonError;
 
 /// Tiled Tileset, containing everything we need to render tiles from
 /// this set as well as decide how to do collision checks
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct Tileset {
     /// Name of the tileset specified by its creator
     pub name: String,
@@ -22,6 +26,9 @@ pub struct Tileset {
     /// are meaningless unless applied to a list of Tilesets associated with
     /// the correct map.
     pub firstgid: GlobalTile,
+    /// Whether this tileset was embedded in the level or loaded from its own file
+    #[serde(skip_deserializing)]
+    pub origin: TilesetOrigin,
     
     /// Number of tiles contained in this map
     pub tilecount: u32,
@@ -45,7 +52,8 @@ pub struct Tileset {
     pub spacing: u32,
     
     /// Key-Value pair properties specified for this tileset (game-specific data)
-    pub properties: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub properties: Properties,
     /// List of all the terrain types defined in this tileset. The values inside
     /// the `tiles` member correspond to indices in this array
     pub terrains: Option<Vec<Terrain>>,
@@ -54,12 +62,142 @@ pub struct Tileset {
     /// List of tiles that are associated with specific terrain, and which
     /// corners belong to which terrain type.
     pub tiles: TileTerrain,
+    /// The index in the level's `tilesets` this tileset was loaded from,
+    /// when `LoadOptions::record_source_pointers` is set. See
+    /// `source_pointer`.
+    #[serde(skip_deserializing)]
+    pub source_index: Option<usize>,
+}
+
+/// Where a Tileset was loaded from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TilesetOrigin {
+    /// The tileset's data was embedded in the level
+    #[default]
+    Inline,
+    /// The tileset was loaded from an external file. `source` is the resolved
+    /// path of that file.
+    External { source: PathBuf },
 }
 
 impl Tileset {
+    /// An empty tileset with the given name, e.g. to stand in for a tileset
+    /// which hasn't been loaded yet. It has no tiles or image, so
+    /// `contains_tile` is always false for it.
+    pub fn placeholder(name: &str) -> Tileset {
+        Tileset {
+            name: name.to_string(),
+            firstgid: GlobalTile(1),
+            origin: TilesetOrigin::Inline,
+            
+            tilecount: 0,
+            tileheight: 0,
+            tilewidth: 0,
+            
+            columns: 0,
+            image: PathBuf::new(),
+            imageheight: 0,
+            imagewidth: 0,
+            margin: 0,
+            spacing: 0,
+            
+            properties: Properties::new(),
+            terrains: None,
+            tileproperties: TileProperties { tiles: HashMap::new() },
+            tiles: TileTerrain { tiles: HashMap::new(), probabilities: HashMap::new() },
+            source_index: None,
+        }
+    }
+    
+    /// Sets the gap in pixels between the edges of the image and the tiles.
+    /// Like the other `with_` methods, this keeps `columns` fitting the image
+    /// as described on `with_columns`.
+    pub fn with_margin(mut self, margin: u32) -> Tileset {
+        self.margin = margin;
+        self.fit_columns()
+    }
+    
+    /// Sets the gap in pixels between neighbouring tiles in the image
+    pub fn with_spacing(mut self, spacing: u32) -> Tileset {
+        self.spacing = spacing;
+        self.fit_columns()
+    }
+    
+    /// Sets the number of tiles in each row of the image. The other `with_`
+    /// methods fix this up when the settings contradict it, as Tiled works
+    /// the columns out from the image: a tileset with tiles but 0 columns
+    /// gets as many columns as fit across the image (or a single row if the
+    /// image or tile size isn't set yet), and one with more columns than fit
+    /// is cut down to those which do.
+    pub fn with_columns(mut self, columns: u32) -> Tileset {
+        self.columns = columns;
+        self.fit_columns()
+    }
+    
+    /// Sets the number of tiles in the set
+    pub fn with_tilecount(mut self, tilecount: u32) -> Tileset {
+        self.tilecount = tilecount;
+        self.fit_columns()
+    }
+    
+    /// Sets the size in pixels of each tile
+    pub fn with_tilesize(mut self, width: u32, height: u32) -> Tileset {
+        self.tilewidth = width;
+        self.tileheight = height;
+        self.fit_columns()
+    }
+    
+    /// Sets the size in pixels of the image
+    pub fn with_image_size(mut self, width: u32, height: u32) -> Tileset {
+        self.imagewidth = width;
+        self.imageheight = height;
+        self.fit_columns()
+    }
+    
+    /// Fixes `columns` where it contradicts the other settings, see
+    /// `with_columns`
+    fn fit_columns(mut self) -> Tileset {
+        // How many tiles fit across the image, if the sizes are known
+        let fit = match (self.tilewidth, self.imagewidth) {
+            (0, _) | (_, 0) => None,
+            (tilewidth, imagewidth) => {
+                let usable = (imagewidth as u64 + self.spacing as u64).saturating_sub(self.margin as u64);
+                Some((usable / (tilewidth as u64 + self.spacing as u64)) as u32)
+            }
+        };
+        match fit {
+            _ if self.columns == 0 && self.tilecount > 0 => self.columns = fit.unwrap_or(self.tilecount),
+            Some(fit) if fit > 0 && self.columns > fit => self.columns = fit,
+            _ => {}
+        }
+        self
+    }
+    
+    /// Finishes building a tileset, e.g. one started with `placeholder` and
+    /// the `with_` methods. Those only fix the columns, so everything else
+    /// is checked here once all the settings have been made: the tiles must
+    /// fit in the image (see `geometry_problems`) and the firstgid must be
+    /// valid.
+    pub fn build(self) -> Result<Tileset, TiledError> {
+        let mut problems: Vec<String> = self.firstgid_problem().into_iter().collect();
+        problems.extend(self.geometry_problems());
+        if problems.is_empty() {
+            Ok(self)
+        } else {
+            Err(TiledError::InvalidTileset(problems))
+        }
+    }
+    
     /// Given a JsonValue for a tileset, and the path of the level it is a member of,
     /// try to parse the tileset or load and parse it from an external file.
     pub fn load<P: AsRef<OsStr>>(data: JsonValue, data_path: &P) -> Result<Tileset, JsonError> {
+        Tileset::load_cached(data, data_path, &mut HashMap::new())
+    }
+    
+    /// Like `load`, but external tileset files are only read the first time
+    /// they are seen; after that they are copied from `cache`, which maps
+    /// resolved paths to the tilesets loaded from them
+    pub(crate) fn load_cached<P: AsRef<OsStr>>(data: JsonValue, data_path: &P, cache: &mut HashMap<PathBuf, Tileset>) -> Result<Tileset, JsonError> {
         use serde::de::Error;
         // The data we're deserializing here must be a Json table
         let mut data = match data {
@@ -74,62 +212,470 @@ impl Tileset {
                 // firstgid is not stored in the external data, so we
                 // must save it from here for later
                 let firstgid = match data.remove("firstgid").and_then(|i| i.as_u64()) {
-                    Some(i) => i as u32,
+                    Some(i) if i <= u32::MAX as u64 => i as u32,
+                    Some(i) => return Err(JsonError::custom(format!("firstgid {} is too large", i))),
                     None => return Err(JsonError::custom("Tileset had no firstgid")),
                 };
                 
-                // Start with the path to the level
-                let mut path = PathBuf::from(data_path);
-                path.pop(); // Path is now the level directory
-                path.push(source); // Path is the tileset to load
-                
-                // Try to open the file! We can just use the try!() macro
-                // because serde_json::Error has a From converion from io::Error
-                let mut file = try!(File::open(&path));
-                
-                // Parse the tileset file into an ExternalTileset structure
-                let ext: ExternalTileset = try!(serde_json::from_reader(&mut file));
-                
-                path.pop();
-                path.push(&ext.image);
-                
-                Tileset {
-                    name: ext.name,
-                    firstgid: GlobalTile(firstgid),
-                    
-                    tilecount: ext.tilecount,
-                    tileheight: ext.tileheight,
-                    tilewidth: ext.tilewidth,
-                    
-                    columns: ext.columns,
-                    image: path,
-                    imageheight: ext.imageheight,
-                    imagewidth: ext.imagewidth,
-                    margin: ext.margin,
-                    spacing: ext.spacing,
-                    
-                    properties: ext.properties,
-                    terrains: ext.terrains,
-                    tileproperties: ext.tileproperties,
-                    tiles: ext.tiles,
-                }
+                // The source is relative to the level
+                let path = resolve_relative(Path::new(data_path), &source);
+                let mut tileset = match cache.get(&path) {
+                    Some(cached) => cached.clone(),
+                    None => {
+                        let tileset = Tileset::load_external(path.clone(), GlobalTile(firstgid))?;
+                        cache.insert(path, tileset.clone());
+                        tileset
+                    }
+                };
+                tileset.firstgid = GlobalTile(firstgid);
+                tileset
             },
             // The tileset is inlined in the level, just parse its data
             _ => {
-                let mut tileset: Tileset = try!(serde_json::from_value(JsonValue::Object(data)));
-                let mut path = PathBuf::from(data_path);
-                path.pop();
-                path.push(&tileset.image);
-                tileset.image = path;
+                let mut tileset: Tileset = from_value_at_field(&JsonValue::Object(data)).map_err(JsonError::custom)?;
+                tileset.image = resolve_relative(Path::new(data_path), &tileset.image.to_string_lossy());
                 tileset
             }
         })
     }
     
+    /// Loads the tileset file at `path`, which is how the tileset is found
+    /// relative to the working directory
+    fn load_external(path: PathBuf, firstgid: GlobalTile) -> Result<Tileset, JsonError> {
+        // Try to open the file! We can just use the ? operator
+        // because serde_json::Error has a From converion from io::Error
+        let _span = load_span!("load_tileset", path = %path.display());
+        load_debug!("Opening tileset {}", path.display());
+        // Parse the tileset file into an ExternalTileset structure
+        let ext: ExternalTileset = read_json(&path)?;
+        
+        // The image is relative to the tileset file
+        let image = resolve_relative(&path, &ext.image);
+        let mut tileset = ext.into_tileset(firstgid, image);
+        tileset.origin = TilesetOrigin::External { source: path };
+        Ok(tileset)
+    }
+    
+    /// The resolved path of the file this tileset was loaded from, or None if
+    /// it was embedded in the level
+    pub fn source_path(&self) -> Option<&Path> {
+        match self.origin {
+            TilesetOrigin::External { ref source } => Some(source),
+            TilesetOrigin::Inline => None,
+        }
+    }
+    
+    /// Reads an external tileset's file again, e.g. after an artist added
+    /// tiles to it, and returns whether anything changed. `firstgid` is kept,
+    /// since it comes from the level. Tilesets embedded in the level have
+    /// nothing to reload and always return false.
+    pub fn reload(&mut self) -> Result<bool, JsonError> {
+        match self.reloaded()? {
+            Some(tileset) => {
+                *self = tileset;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    
+    /// Reads an external tileset's file again, giving the new tileset if it
+    /// differs from this one and leaving this one alone
+    pub(crate) fn reloaded(&self) -> Result<Option<Tileset>, JsonError> {
+        let source = match self.source_path() {
+            Some(source) => source.to_path_buf(),
+            None => return Ok(None),
+        };
+        
+        let mut tileset = Tileset::load_external(source, self.firstgid)?;
+        // Where the tileset sits in the level isn't part of its file
+        tileset.source_index = self.source_index;
+        if tileset == *self {
+            Ok(None)
+        } else {
+            Ok(Some(tileset))
+        }
+    }
+    
+    /// Where in the level's JSON this tileset was loaded from, e.g.
+    /// `/tilesets/1`, if the level was loaded with
+    /// `LoadOptions::record_source_pointers`
+    pub fn source_pointer(&self) -> Option<String> {
+        self.source_index.map(|index| format!("/tilesets/{}", index))
+    }
+    
+    /// Whether `id` refers to a tile of this set, ignoring flip flags
     pub fn contains_tile(&self, id: GlobalTile) -> bool {
-        if id.0 < self.firstgid.0 { return false; }
-        let local = id.0 - self.firstgid.0;
-        local < self.tilecount
+        let id = id.strip_flags();
+        if id.is_empty() { return false; }
+        match id.0.checked_sub(self.firstgid.0) {
+            Some(local) => local < self.tilecount,
+            None => false,
+        }
+    }
+    
+    /// The custom properties of a single tile, which are empty if the tile
+    /// has none
+    pub fn properties_for_tile(&self, tile: LocalTile) -> HashMap<&str, &str> {
+        match self.tileproperties.tiles.get(&tile) {
+            Some(properties) => properties.iter().map(|(key, value)| (&key[..], &value[..])).collect(),
+            None => HashMap::new(),
+        }
+    }
+    
+    /// The value of one custom property of a tile
+    pub fn get_tile_property(&self, tile: LocalTile, key: &str) -> Option<&str> {
+        self.tileproperties.tiles.get(&tile).and_then(|properties| properties.get_str(key))
+    }
+    
+    /// How likely the tile is to be chosen when Tiled paints with several
+    /// tiles at once, as set by the artist. Tiles without one have Tiled's
+    /// default of 1.
+    pub fn tile_probability(&self, tile: LocalTile) -> f32 {
+        self.tiles.probabilities.get(&tile).cloned().unwrap_or(1.0)
+    }
+    
+    /// Picks one of `candidates` weighted by `tile_probability`, where `roll`
+    /// is a random number in `0..1` from the caller's own RNG. Tiles with a
+    /// probability of 0 are never picked, unless every candidate has one, in
+    /// which case each is equally likely. `None` if there are no candidates.
+    pub fn weighted_pick(&self, candidates: &[LocalTile], roll: f32) -> Option<LocalTile> {
+        // Anything which can't be a weight counts as 0
+        let weight = |tile: LocalTile| {
+            let probability = self.tile_probability(tile);
+            if probability > 0.0 && probability.is_finite() { probability } else { 0.0 }
+        };
+        let roll = if roll > 0.0 { roll.min(1.0) } else { 0.0 };
+        
+        let total: f32 = candidates.iter().map(|&tile| weight(tile)).sum();
+        if total <= 0.0 {
+            let index = (roll * candidates.len() as f32) as usize;
+            return candidates.get(min(index, candidates.len().saturating_sub(1))).cloned();
+        }
+        
+        let target = roll * total;
+        let mut reached = 0.0;
+        let mut last = None;
+        for &tile in candidates {
+            let weight = weight(tile);
+            if weight == 0.0 {
+                continue;
+            }
+            reached += weight;
+            last = Some(tile);
+            if target < reached {
+                break;
+            }
+        }
+        // A roll of 1, or rounding in the sum, lands past the end
+        last
+    }
+    
+    /// Describes what is wrong with `firstgid`, if anything. Gids start at 1,
+    /// and the last tile's gid must fit below the flip flags in the top bits.
+    pub fn firstgid_problem(&self) -> Option<String> {
+        let last = self.firstgid.0 as u64 + self.tilecount as u64;
+        if self.firstgid.0 == 0 {
+            Some(format!("Tileset `{}` has firstgid 0, but gids start at 1", self.name))
+        } else if self.tilecount > 0 && last - 1 > !FLIP_FLAGS as u64 {
+            Some(format!(
+                "Tileset `{}` has firstgid {}, so its last tile's gid {} is too large to store",
+                self.name, self.firstgid.0, last - 1
+            ))
+        } else {
+            None
+        }
+    }
+    
+    /// Whether `other` cuts its image into the same tiles as this tileset: the
+    /// same number of tiles and columns, tile size, margin and spacing
+    pub fn same_grid(&self, other: &Tileset) -> bool {
+        (self.tilecount, self.columns, self.tilewidth, self.tileheight, self.margin, self.spacing)
+            == (other.tilecount, other.columns, other.tilewidth, other.tileheight, other.margin, other.spacing)
+    }
+    
+    /// Number of rows of tiles which fit in the tileset image, going by the
+    /// declared image size, tile size, margin and spacing.
+    pub fn rows(&self) -> u32 {
+        cells_within(self.imageheight, self.tileheight, self.margin, self.spacing)
+    }
+    
+    /// The area of the tileset image showing `tile`, or None if the tile
+    /// isn't part of this set. See `cell_start` for the layout of tiles.
+    pub fn tile_rect(&self, tile: LocalTile) -> Option<TileRect> {
+        if tile.0 >= self.tilecount || self.columns == 0 {
+            return None;
+        }
+        let column = tile.0 % self.columns;
+        let row = tile.0 / self.columns;
+        let x = cell_start(column, self.tilewidth, self.margin, self.spacing);
+        let y = cell_start(row, self.tileheight, self.margin, self.spacing);
+        match (u32::try_from(x), u32::try_from(y)) {
+            (Ok(x), Ok(y)) => Some(TileRect { x, y, width: self.tilewidth, height: self.tileheight }),
+            _ => None,
+        }
+    }
+    
+    /// Pixel position of the top-left corner of `tile` in the tileset image
+    pub fn tile_position(&self, tile: LocalTile) -> Option<(u32, u32)> {
+        self.tile_rect(tile).map(|rect| (rect.x, rect.y))
+    }
+    
+    /// Texture coordinates of `tile` as `[left, top, right, bottom]`, where
+    /// (0, 0) is the top-left and (1, 1) the bottom-right of the image
+    pub fn tile_uv(&self, tile: LocalTile) -> Option<[f32; 4]> {
+        if self.imagewidth == 0 || self.imageheight == 0 {
+            return None;
+        }
+        self.tile_rect(tile).map(|rect| {
+            let w = self.imagewidth as f32;
+            let h = self.imageheight as f32;
+            [
+                rect.x as f32 / w,
+                rect.y as f32 / h,
+                (rect.x + rect.width) as f32 / w,
+                (rect.y + rect.height) as f32 / h,
+            ]
+        })
+    }
+    
+    /// The tile drawn at a pixel of the tileset image, or None if the pixel is
+    /// in the margin, in the spacing between tiles, or past the last tile
+    pub fn tile_at_pixel(&self, x: u32, y: u32) -> Option<LocalTile> {
+        let column = match cell_at(x, self.tilewidth, self.margin, self.spacing) {
+            Some(column) if column < self.columns => column,
+            _ => return None,
+        };
+        let row = match cell_at(y, self.tileheight, self.margin, self.spacing) {
+            Some(row) => row as u64,
+            None => return None,
+        };
+        let index = row * self.columns as u64 + column as u64;
+        if index < self.tilecount as u64 {
+            Some(LocalTile(index as u32))
+        } else {
+            None
+        }
+    }
+    
+    /// Calls `f` with every tile of the set in id order, along with everything
+    /// known about the tile
+    pub fn for_each_tile<F: FnMut(LocalTile, TileInfo)>(&self, mut f: F) {
+        for id in 0..self.tilecount {
+            let tile = LocalTile(id);
+            f(tile, TileInfo {
+                source_rect: self.tile_rect(tile),
+                properties: self.tileproperties.tiles.get(&tile),
+                terrain: self.tiles.tiles.get(&tile).cloned(),
+            });
+        }
+    }
+    
+    /// Number of tiles which actually have image data, `min(tilecount, rows * columns)`.
+    /// Tiled allows `tilecount` to be less than `rows * columns` when the last
+    /// row of the image is incomplete, but if `tilecount` is larger than the
+    /// image can hold, source rectangles for tiles in
+    /// `effective_tilecount..tilecount` will be outside of the image or point
+    /// at transparent regions.
+    pub fn effective_tilecount(&self) -> u32 {
+        min(self.tilecount, self.rows().saturating_mul(self.columns))
+    }
+    
+    /// Checks that the declared grid of tiles fits inside the declared image
+    /// size, returning a description of each problem found. Tiles are laid out
+    /// starting `margin` pixels from the top-left of the image with `spacing`
+    /// pixels between each, so a row needs
+    /// `margin + columns * tilewidth + (columns - 1) * spacing` pixels.
+    pub fn geometry_problems(&self) -> Vec<String> {
+        fn extent(count: u32, size: u32, margin: u32, spacing: u32) -> u64 {
+            if count == 0 {
+                return 0;
+            }
+            cell_start(count - 1, size, margin, spacing) + size as u64
+        }
+        
+        let mut problems = Vec::new();
+        if self.columns == 0 {
+            if self.tilecount > 0 {
+                problems.push(format!("Tileset `{}` has {} tiles but 0 columns", self.name, self.tilecount));
+            }
+            return problems;
+        }
+        
+        let rows = (self.tilecount as u64).div_ceil(self.columns as u64) as u32;
+        let width = extent(self.columns, self.tilewidth, self.margin, self.spacing);
+        let height = extent(rows, self.tileheight, self.margin, self.spacing);
+        if width > self.imagewidth as u64 {
+            problems.push(format!(
+                "Tileset `{}` needs an image {} pixels wide for {} columns, but its imagewidth is {}",
+                self.name, width, self.columns, self.imagewidth
+            ));
+        }
+        if height > self.imageheight as u64 {
+            problems.push(format!(
+                "Tileset `{}` needs an image {} pixels tall for {} rows, but its imageheight is {}",
+                self.name, height, rows, self.imageheight
+            ));
+        }
+        problems
+    }
+    
+    /// This tileset as JSON in the form Tiled embeds it in a level, with all
+    /// of its data. `base_path` is the level it will be embedded in, and the
+    /// image is written relative to it.
+    pub fn to_json_value_inline(&self, base_path: &Path) -> JsonValue {
+        fn number(n: u32) -> JsonValue {
+            JsonValue::from(n)
+        }
+        
+        let mut data = JsonMap::new();
+        data.insert("name".to_string(), JsonValue::String(self.name.clone()));
+        data.insert("firstgid".to_string(), number(self.firstgid.0));
+        data.insert("tilecount".to_string(), number(self.tilecount));
+        data.insert("tileheight".to_string(), number(self.tileheight));
+        data.insert("tilewidth".to_string(), number(self.tilewidth));
+        data.insert("columns".to_string(), number(self.columns));
+        data.insert("image".to_string(), JsonValue::String(relative_path(base_path, &self.image)));
+        data.insert("imageheight".to_string(), number(self.imageheight));
+        data.insert("imagewidth".to_string(), number(self.imagewidth));
+        data.insert("margin".to_string(), number(self.margin));
+        data.insert("spacing".to_string(), number(self.spacing));
+        
+        if !self.properties.is_empty() {
+            data.insert("properties".to_string(), self.properties.to_json_value());
+        }
+        if let Some(ref terrains) = self.terrains {
+            data.insert("terrains".to_string(), JsonValue::Array(terrains.iter().map(|terrain| {
+                let mut data = JsonMap::new();
+                data.insert("name".to_string(), JsonValue::String(terrain.name.clone()));
+                data.insert("tile".to_string(), number(terrain.tile.0));
+                JsonValue::Object(data)
+            }).collect()));
+        }
+        
+        // Tiled keys these by the tile id as a string
+        data.insert("tileproperties".to_string(), JsonValue::Object(
+            self.tileproperties.tiles.iter().map(|(tile, properties)| {
+                (tile.0.to_string(), properties.to_json_value())
+            }).collect()
+        ));
+        let mut tiles = BTreeMap::new();
+        for (tile, terrain) in &self.tiles.tiles {
+            let data = tiles.entry(tile.0.to_string()).or_insert_with(JsonMap::new);
+            data.insert("terrain".to_string(), JsonValue::Array(terrain.iter().map(|&t| number(t)).collect()));
+        }
+        for (tile, &probability) in &self.tiles.probabilities {
+            let data = tiles.entry(tile.0.to_string()).or_insert_with(JsonMap::new);
+            data.insert("probability".to_string(), JsonValue::from(probability as f64));
+        }
+        data.insert("tiles".to_string(), JsonValue::Object(
+            tiles.into_iter().map(|(tile, data)| (tile, JsonValue::Object(data))).collect()
+        ));
+        
+        JsonValue::Object(data)
+    }
+    
+    /// The entry a level uses to refer to this tileset when it is stored in
+    /// its own file, `{"firstgid": N, "source": "..."}`. `source_path` is where
+    /// the tileset file is and `base_path` is the level referring to it, and
+    /// the source is written relative to the level.
+    pub fn to_json_value_external(&self, base_path: &Path, source_path: &Path) -> JsonValue {
+        let mut data = JsonMap::new();
+        data.insert("firstgid".to_string(), JsonValue::from(self.firstgid.0));
+        data.insert("source".to_string(), JsonValue::String(relative_path(base_path, source_path)));
+        JsonValue::Object(data)
+    }
+    
+    /// How far tiles from this set extend past a map grid cell of the given
+    /// size. Tiles are drawn anchored to the bottom-left corner of their cell,
+    /// so oversized tiles overflow to the right and upwards. Values are negative
+    /// when tiles are smaller than the grid.
+    pub fn overhang(&self, map_tile_w: u32, map_tile_h: u32) -> (i32, i32) {
+        (
+            self.tilewidth as i32 - map_tile_w as i32,
+            self.tileheight as i32 - map_tile_h as i32,
+        )
+    }
+}
+
+/// Everything a tileset says about one of its tiles, see
+/// `Tileset::for_each_tile`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TileInfo<'a> {
+    /// Where the tile is in the tileset image, see `Tileset::tile_rect`
+    pub source_rect: Option<TileRect>,
+    /// The tile's custom properties, if it has any
+    pub properties: Option<&'a Properties>,
+    /// The terrain at each corner of the tile, if it has terrain information
+    pub terrain: Option<[u32; 4]>,
+}
+
+/// An area of a tileset image in pixels, measured from the top-left
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TileRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Every calculation which slices up a tileset image goes through the
+// functions below, one axis at a time, so that they all agree on where tiles
+// are. Tiles start `margin` pixels from the edge of the image with `spacing`
+// pixels between neighbours, so cell `n` starts at
+// `margin + n * (size + spacing)` and ends `size` pixels later.
+
+/// Where cell `index` starts along an axis
+fn cell_start(index: u32, size: u32, margin: u32, spacing: u32) -> u64 {
+    margin as u64 + index as u64 * (size as u64 + spacing as u64)
+}
+
+/// The cell covering pixel `pixel` along an axis, if it isn't in the margin
+/// or spacing
+fn cell_at(pixel: u32, size: u32, margin: u32, spacing: u32) -> Option<u32> {
+    let step = size as u64 + spacing as u64;
+    if pixel < margin || size == 0 {
+        return None;
+    }
+    let offset = (pixel - margin) as u64;
+    if offset % step < size as u64 {
+        Some((offset / step) as u32)
+    } else {
+        None
+    }
+}
+
+/// How many whole cells fit in `extent` pixels along an axis
+fn cells_within(extent: u32, size: u32, margin: u32, spacing: u32) -> u32 {
+    let step = size as u64 + spacing as u64;
+    if step == 0 || (extent as u64) < margin as u64 + size as u64 {
+        return 0;
+    }
+    ((extent as u64 - margin as u64 + spacing as u64) / step) as u32
+}
+
+impl<'a> TryFrom<&'a str> for Tileset {
+    type Error = TiledError;
+    
+    /// Parses a tileset from JSON, either in the form it is inlined in a level
+    /// or as a standalone tileset file. Standalone tilesets don't have a
+    /// firstgid, so they are given a firstgid of 1. The image path is left
+    /// as written in the JSON.
+    fn try_from(json: &'a str) -> Result<Tileset, TiledError> {
+        let data: JsonValue = parse_json(json.as_bytes())?;
+        match data {
+            JsonValue::Object(ref data) if data.contains_key("source") => {
+                return Err(TiledError::ExternalTilesetNotSupported);
+            }
+            JsonValue::Object(ref data) if !data.contains_key("firstgid") => {
+                let ext: ExternalTileset = serde_json::from_value(JsonValue::Object(data.clone()))?;
+                let image = PathBuf::from(&ext.image);
+                return Ok(ext.into_tileset(GlobalTile(1), image));
+            }
+            _ => {}
+        }
+        
+        Ok(Tileset::load(data, &"")?)
     }
 }
 
@@ -142,30 +688,59 @@ struct ExternalTileset {
     tilewidth: u32,
     
     columns: u32,
-    image: PathBuf,
+    image: String,
     imageheight: u32,
     imagewidth: u32,
     margin: u32,
     spacing: u32,
     
-    properties: Option<HashMap<String, String>>,
+    #[serde(default)]
+    properties: Properties,
     terrains: Option<Vec<Terrain>>,
     tileproperties: TileProperties,
     tiles: TileTerrain,
 }
 
-#[derive(Clone, Debug)]
+impl ExternalTileset {
+    /// Completes the tileset with the data which is stored in the level
+    /// rather than the tileset file
+    fn into_tileset(self, firstgid: GlobalTile, image: PathBuf) -> Tileset {
+        Tileset {
+            name: self.name,
+            firstgid,
+            origin: TilesetOrigin::Inline,
+            
+            tilecount: self.tilecount,
+            tileheight: self.tileheight,
+            tilewidth: self.tilewidth,
+            
+            columns: self.columns,
+            image,
+            imageheight: self.imageheight,
+            imagewidth: self.imagewidth,
+            margin: self.margin,
+            spacing: self.spacing,
+            
+            properties: self.properties,
+            terrains: self.terrains,
+            tileproperties: self.tileproperties,
+            tiles: self.tiles,
+            source_index: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct TileProperties {
-    pub tiles: HashMap<LocalTile, HashMap<String, String>>,
+    pub tiles: HashMap<LocalTile, Properties>,
 }
 
-impl Deserialize for TileProperties {
-    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+impl<'de> Deserialize<'de> for TileProperties {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         // Tiled uses string keys because it's a sparse array,
         // so we're just going to parse it like that and then
         // convert them to LocalTiles
-        let data: HashMap<String, HashMap<String, String>>;
-        data = try!(Deserialize::deserialize(d));
+        let data: HashMap<String, Properties> = Deserialize::deserialize(d)?;
         
         let mut props = HashMap::new();
         for (k, v) in data {
@@ -187,25 +762,30 @@ impl Deserialize for TileProperties {
     }
 }
 
-#[derive(Clone, Debug)]
+/// The per-tile settings from a tileset's `tiles`
+#[derive(Clone, Debug, PartialEq)]
 pub struct TileTerrain {
-    pub tiles: HashMap<LocalTile, [u32; 4]>
+    pub tiles: HashMap<LocalTile, [u32; 4]>,
+    /// The probability of each tile which has one set, see
+    /// `Tileset::tile_probability`
+    pub probabilities: HashMap<LocalTile, f32>,
 }
 
-impl Deserialize for TileTerrain {
-    fn deserialize<D: Deserializer>(d: &mut D) -> Result<Self, D::Error> {
+impl<'de> Deserialize<'de> for TileTerrain {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         #[derive(Deserialize)]
         struct Data {
-            terrain: [u32; 4]
+            terrain: Option<[u32; 4]>,
+            probability: Option<f32>,
         }
         
         // Tiled uses string keys because it's a sparse array,
         // so we're just going to parse it like that and then
         // convert them to LocalTiles
-        let data: HashMap<String, Data>;
-        data = try!(Deserialize::deserialize(d));
+        let data: HashMap<String, Data> = Deserialize::deserialize(d)?;
         
         let mut terrains = HashMap::new();
+        let mut probabilities = HashMap::new();
         for (k, v) in data {
             // Allows us to return an error when a bad key is present
             use serde::de::Error;
@@ -216,21 +796,39 @@ impl Deserialize for TileTerrain {
                 Err(_) => return Err(D::Error::custom("tileproperties contained a non-integer key"))
             };
             
-            terrains.insert(LocalTile(id), v.terrain);
+            if let Some(terrain) = v.terrain {
+                terrains.insert(LocalTile(id), terrain);
+            }
+            if let Some(probability) = v.probability {
+                probabilities.insert(LocalTile(id), probability);
+            }
         }
         
         Ok(TileTerrain {
             tiles: terrains,
+            probabilities,
         })
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct Terrain {
     pub name: String,
     pub tile: LocalTile,
 }
 
+impl Terrain {
+    /// Finds the index of this terrain in `tileset.terrains`, which is the value
+    /// used to refer to it in `TileTerrain`. Terrains are matched by name, and
+    /// this is O(n) in the number of terrains in the tileset.
+    pub fn index_in(&self, tileset: &Tileset) -> Option<usize> {
+        match tileset.terrains {
+            Some(ref terrains) => terrains.iter().position(|t| t.name == self.name),
+            None => None,
+        }
+    }
+}
+
 /// Test to ensure we can deserialize an ExternalTileset
 #[test]
 fn deserialize_external() {
@@ -239,3 +837,103 @@ fn deserialize_external() {
     let data = include_str!("../test-assets/tilesets/goodly-2x.json");
     let _: ExternalTileset = from_str(data).unwrap();
 }
+
+#[test]
+fn tile_rects_with_margin_and_spacing() {
+    let tileset = Tileset::try_from(include_str!("../test-assets/tilesets/spaced.json")).unwrap();
+    
+    assert_eq!(tileset.rows(), 2);
+    assert!(tileset.geometry_problems().is_empty());
+    assert_eq!(tileset.tile_rect(LocalTile(0)), Some(TileRect { x: 2, y: 2, width: 16, height: 16 }));
+    assert_eq!(tileset.tile_rect(LocalTile(5)), Some(TileRect { x: 36, y: 19, width: 16, height: 16 }));
+    assert_eq!(tileset.tile_rect(LocalTile(6)), None);
+    assert_eq!(tileset.tile_uv(LocalTile(4)), Some([19.0 / 54.0, 19.0 / 37.0, 35.0 / 54.0, 35.0 / 37.0]));
+    
+    // Every pixel of a tile maps back to it, and the gaps map to nothing
+    for tile in 0..6 {
+        let rect = tileset.tile_rect(LocalTile(tile)).unwrap();
+        assert_eq!(tileset.tile_at_pixel(rect.x, rect.y), Some(LocalTile(tile)));
+        assert_eq!(tileset.tile_at_pixel(rect.x + 15, rect.y + 15), Some(LocalTile(tile)));
+        assert_eq!(tileset.tile_at_pixel(rect.x + 16, rect.y), None);
+    }
+    assert_eq!(tileset.tile_at_pixel(1, 2), None);
+    assert_eq!(tileset.tile_at_pixel(2, 18), None);
+}
+
+#[test]
+fn firstgid_out_of_range() {
+    let mut tileset = Tileset::placeholder("tiles");
+    tileset.tilecount = 4;
+    assert_eq!(tileset.firstgid_problem(), None);
+    
+    tileset.firstgid = GlobalTile(0);
+    assert!(tileset.firstgid_problem().is_some());
+    assert!(!tileset.contains_tile(GlobalTile(0)));
+    assert!(tileset.contains_tile(GlobalTile(3)));
+    
+    tileset.firstgid = GlobalTile(!FLIP_FLAGS - 2);
+    assert!(tileset.firstgid_problem().is_some());
+    assert!(!tileset.contains_tile(GlobalTile(1)));
+    assert_eq!(GlobalTile(1).find_local(&[tileset]), None);
+}
+
+#[test]
+fn weighted_tile_picks() {
+    let mut tileset = Tileset::placeholder("decor");
+    tileset.tilecount = 4;
+    tileset.tiles.probabilities.insert(LocalTile(1), 0.0);
+    tileset.tiles.probabilities.insert(LocalTile(2), 3.0);
+    
+    // Tile 0 has the default weight of 1, so takes the first quarter
+    let candidates = [LocalTile(0), LocalTile(1), LocalTile(2)];
+    assert_eq!(tileset.weighted_pick(&candidates, 0.2), Some(LocalTile(0)));
+    assert_eq!(tileset.weighted_pick(&candidates, 0.25), Some(LocalTile(2)));
+    assert_eq!(tileset.weighted_pick(&candidates, 1.0), Some(LocalTile(2)));
+    for i in 0..100 {
+        assert!(tileset.weighted_pick(&candidates, i as f32 / 100.0) != Some(LocalTile(1)));
+    }
+    
+    // With nothing to weigh, every candidate is equally likely
+    tileset.tiles.probabilities.insert(LocalTile(0), 0.0);
+    let zeros = [LocalTile(0), LocalTile(1)];
+    assert_eq!(tileset.weighted_pick(&zeros, 0.4), Some(LocalTile(0)));
+    assert_eq!(tileset.weighted_pick(&zeros, 0.6), Some(LocalTile(1)));
+    assert_eq!(tileset.weighted_pick(&zeros, 1.0), Some(LocalTile(1)));
+    assert_eq!(tileset.weighted_pick(&[], 0.5), None);
+}
+
+#[test]
+fn inline_json_keeps_paths_and_types() {
+    use level::Level;
+    
+    let level = Level::load("test-assets/levels/simple2.json").unwrap();
+    let mut tileset = (*level.tilesets[0]).clone();
+    tileset.properties = serde_json::from_str(r#"[
+        { "name": "solid", "type": "bool", "value": true },
+        { "name": "speed", "type": "int", "value": 3 },
+        { "name": "label", "type": "string", "value": "grass" }
+    ]"#).unwrap();
+    
+    // Embedded in a level next to the original one
+    let json = tileset.to_json_value_inline(Path::new("test-assets/levels/merged.json"));
+    assert_eq!(json["image"], JsonValue::from("../tilesets/goodly-2x.png"));
+    assert_eq!(json.pointer("/properties/2/value"), Some(&JsonValue::from(3)));
+    let properties: Properties = serde_json::from_value(json["properties"].clone()).unwrap();
+    assert_eq!(properties, tileset.properties);
+}
+
+#[test]
+fn builder_fits_columns_to_the_image() {
+    // Before the image size is known the tiles go in one row
+    let tileset = Tileset::placeholder("tiles").with_tilesize(16, 16).with_tilecount(4);
+    assert_eq!(tileset.columns, 4);
+    let tileset = tileset.with_image_size(32, 32).build().unwrap();
+    assert_eq!(tileset.columns, 2);
+    
+    let tileset = Tileset::placeholder("tiles")
+        .with_image_size(37, 37).with_tilesize(16, 16).with_columns(0).with_tilecount(4);
+    assert_eq!(tileset.columns, 2);
+    assert_eq!(tileset.clone().with_margin(1).with_spacing(5).columns, 1);
+    assert_eq!(tileset.clone().with_margin(1).with_spacing(3).columns, 2);
+    assert!(tileset.with_columns(3).with_tilecount(6).with_image_size(32, 32).build().is_err());
+}